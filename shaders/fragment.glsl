@@ -3,6 +3,7 @@
 precision mediump float;
 
 uniform sampler2D uTexture;
+uniform float uOpacity;
 
 varying vec2 vTextureCoord;
 
@@ -11,4 +12,5 @@ void main()
     // Transform vertex to texture coordinates.
     vec2 coord = vec2(0.5 * vTextureCoord.x + 0.5, -0.5 * vTextureCoord.y + 0.5);
     gl_FragColor = texture2D(uTexture, coord);
+    gl_FragColor.a *= uOpacity;
 }
@@ -0,0 +1,53 @@
+#version 100
+
+#ifdef GL_FRAGMENT_PRECISION_HIGH
+precision highp float;
+#else
+precision mediump float;
+#endif
+
+uniform int uKind;
+uniform vec3 uColors[2];
+uniform float uCellSize;
+uniform vec2 uResolution;
+
+varying vec2 vPosition;
+
+// Pseudo-random value for a noise lattice point.
+float hash(vec2 point)
+{
+    return fract(sin(dot(point, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+void main()
+{
+    // Use a top-left origin, matching the software renderer.
+    vec2 position = vPosition * uResolution;
+    vec2 cell = position / uCellSize;
+
+    float t;
+    if (uKind == 0) {
+        // Checkerboard.
+        t = mod(floor(cell.x) + floor(cell.y), 2.);
+    } else if (uKind == 1) {
+        // Diagonal stripes.
+        t = step(0.5, fract(cell.x + cell.y));
+    } else if (uKind == 2) {
+        // Antialiased dots at the center of each cell.
+        float distance = length(fract(cell) - 0.5) * uCellSize;
+        t = clamp(0.25 * uCellSize - distance + 0.5, 0., 1.);
+    } else if (uKind == 3) {
+        // Smoothly interpolated value noise.
+        vec2 i = floor(cell);
+        vec2 f = fract(cell);
+        vec2 u = f * f * (3. - 2. * f);
+        float top = mix(hash(i), hash(i + vec2(1., 0.)), u.x);
+        float bottom = mix(hash(i + vec2(0., 1.)), hash(i + vec2(1., 1.)), u.x);
+        t = mix(top, bottom, u.y);
+    } else {
+        // Vertical sky gradient.
+        t = position.y / uResolution.y;
+    }
+
+    gl_FragColor = vec4(mix(uColors[0], uColors[1], t), 1.);
+}
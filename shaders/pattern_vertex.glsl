@@ -0,0 +1,16 @@
+#version 100
+
+attribute vec2 aVertexPosition;
+
+uniform mat2 uTransform;
+
+varying vec2 vPosition;
+
+void main()
+{
+    // Cover the entire viewport, pre-rotated for the buffer transform.
+    gl_Position = vec4(uTransform * aVertexPosition, 0., 1.);
+
+    // Pass the relative position before the transform.
+    vPosition = vec2(aVertexPosition.x + 1., 1. - aVertexPosition.y) * 0.5;
+}
@@ -4,6 +4,7 @@ attribute vec2 aVertexPosition;
 
 uniform vec2 uPosition;
 uniform mat2 uMatrix;
+uniform mat2 uTransform;
 
 varying vec2 vTextureCoord;
 
@@ -15,7 +16,8 @@ void main()
     // Apply texture size transform and move texture anchor target to top-left of the screen.
     vertexPosition = (uMatrix * vertexPosition) + vec2(-1., 1.);
 
-    gl_Position = vec4(vertexPosition + uPosition, 0., 1.);
+    // Pre-rotate the output for the buffer transform.
+    gl_Position = vec4(uTransform * (vertexPosition + uPosition), 0., 1.);
 
     vTextureCoord = aVertexPosition;
 }
@@ -0,0 +1,37 @@
+//! Blurred image backdrops.
+
+use image::{RgbaImage, imageops};
+
+use crate::cli::{Backdrop, Options};
+use crate::window::UnloadedImage;
+
+/// Maximum dimensions of the blurred image.
+///
+/// Blurring at a low resolution is much cheaper than blurring at the
+/// surface's size, and the result is smoothed further when scaled up.
+const BACKDROP_SIZE: u32 = 48;
+
+/// Standard deviation of the blur, in backdrop pixels.
+const BLUR_SIGMA: f32 = 3.;
+
+/// Brightness of the backdrop, to keep it from competing with the image.
+const BRIGHTNESS: f32 = 0.6;
+
+/// Create the image's backdrop, if enabled.
+///
+/// The backdrop keeps the image's aspect ratio and should be drawn covering
+/// the entire surface.
+pub fn create(options: &Options, image: Option<&UnloadedImage>) -> Option<RgbaImage> {
+    image.filter(|_| options.backdrop == Backdrop::Blur).map(blur)
+}
+
+/// Create a blurred and dimmed copy of an image.
+fn blur(image: &UnloadedImage) -> RgbaImage {
+    let mut backdrop = imageops::blur(&image.thumbnail(BACKDROP_SIZE), BLUR_SIGMA);
+    for pixel in backdrop.pixels_mut() {
+        for channel in &mut pixel.0[..3] {
+            *channel = (*channel as f32 * BRIGHTNESS).round() as u8;
+        }
+    }
+    backdrop
+}
@@ -0,0 +1,442 @@
+//! On-disk cache of decoded images.
+//!
+//! Images are stored after scaling them down for a surface, as raw RGBA data
+//! which can be mapped into memory directly, to skip decoding at startup.
+
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{env, process};
+
+use image::RgbaImage;
+use memmap2::Mmap;
+use tracing::warn;
+
+use crate::cli::Options;
+use crate::geometry::Size;
+use crate::window::UnloadedImage;
+
+/// Cache file signature, including the format version.
+const MAGIC: &[u8; 8] = b"TABULA\x00\x01";
+
+/// Cache file header size, containing signature, width and height.
+const HEADER_SIZE: usize = MAGIC.len() + 8;
+
+/// Cache file extension.
+const EXTENSION: &str = "rgba";
+
+/// Extension of cache files which are still being written.
+const TMP_EXTENSION: &str = "tmp";
+
+/// Age after which temporary files are considered abandoned.
+///
+/// Temporary files are left behind if a process exits while writing them.
+const TMP_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// Interval between checks for finished background scaling.
+const JOB_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Scaled image cache.
+#[derive(Clone)]
+pub struct ImageCache {
+    directory: PathBuf,
+    max_size: u64,
+}
+
+impl ImageCache {
+    /// Open the cache in `$XDG_CACHE_HOME/tabula`, limited to `max_size` bytes.
+    ///
+    /// Returns `None` if caching is disabled with a `max_size` of zero.
+    pub fn new(max_size: u64) -> Option<Self> {
+        if max_size == 0 {
+            return None;
+        }
+
+        let cache_home = env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+            .or_else(|| Some(PathBuf::from(env::var_os("HOME")?).join(".cache")))?;
+
+        Some(Self::with_directory(cache_home.join("tabula"), max_size))
+    }
+
+    fn with_directory(directory: PathBuf, max_size: u64) -> Self {
+        Self { directory, max_size }
+    }
+
+    /// Load a source image scaled for a surface's physical size.
+    ///
+    /// The `scaling` identifies how the image was scaled for the surface.
+    pub fn load(
+        &self,
+        source: &Path,
+        scaling: impl Hash,
+        physical_size: Size,
+    ) -> Option<UnloadedImage> {
+        let identity = identity(source, scaling)?;
+        load_entry(&self.entry_path(identity, physical_size))
+    }
+
+    /// Load the most recently used version of a source image.
+    ///
+    /// This is used before the surface's size is known, returning the image
+    /// and the physical surface size it was scaled for.
+    pub fn load_latest(&self, source: &Path, scaling: impl Hash) -> Option<(UnloadedImage, Size)> {
+        let prefix = format!("{:016x}-", identity(source, scaling)?);
+        let (_, physical_size, path) = self
+            .entries()
+            .filter_map(|(path, modified, _)| {
+                let name = path.file_stem()?.to_str()?;
+                let size = name.strip_prefix(&prefix)?.parse::<Size>().ok()?;
+                Some((modified, size, path))
+            })
+            .max_by_key(|(modified, ..)| *modified)?;
+
+        Some((load_entry(&path)?, physical_size))
+    }
+
+    /// Store a source image scaled for a surface's physical size.
+    ///
+    /// Least recently used images are evicted to stay within the size limit.
+    pub fn store(
+        &self,
+        source: &Path,
+        scaling: impl Hash,
+        physical_size: Size,
+        image: &RgbaImage,
+    ) -> io::Result<()> {
+        let identity = identity(source, scaling).ok_or(io::ErrorKind::NotFound)?;
+
+        // Skip images which would immediately be evicted again.
+        let size = (HEADER_SIZE + image.as_raw().len()) as u64;
+        if size > self.max_size {
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.directory)?;
+
+        // Write to a temporary file first, to never expose partial entries.
+        //
+        // Temporary files must be unique, since wallpapers on multiple outputs
+        // might store the same entry concurrently.
+        static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let tmp_id = TMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = self.entry_path(identity, physical_size);
+        let tmp_path = path.with_extension(format!("{}-{tmp_id}.{TMP_EXTENSION}", process::id()));
+        let result = write_entry(&tmp_path, image).and_then(|_| fs::rename(&tmp_path, &path));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result?;
+
+        self.evict();
+
+        Ok(())
+    }
+
+    /// Remove the least recently used entries exceeding the size limit.
+    ///
+    /// Abandoned temporary files are removed too.
+    fn evict(&self) {
+        let now = SystemTime::now();
+        for (path, modified, _) in self.files(TMP_EXTENSION) {
+            if now.duration_since(modified).is_ok_and(|age| age >= TMP_MAX_AGE) {
+                let _ = fs::remove_file(path);
+            }
+        }
+
+        let mut entries: Vec<_> = self.entries().collect();
+        entries.sort_unstable_by_key(|(_, modified, _)| *modified);
+
+        let mut total_size: u64 = entries.iter().map(|(.., size)| size).sum();
+        for (path, _, size) in entries {
+            if total_size <= self.max_size {
+                break;
+            }
+
+            if fs::remove_file(path).is_ok() {
+                total_size -= size;
+            }
+        }
+    }
+
+    /// Get all cache entries, with their last use and size.
+    fn entries(&self) -> impl Iterator<Item = (PathBuf, SystemTime, u64)> {
+        self.files(EXTENSION)
+    }
+
+    /// Get all files with an extension, with their modification time and size.
+    fn files(&self, extension: &str) -> impl Iterator<Item = (PathBuf, SystemTime, u64)> {
+        fs::read_dir(&self.directory).into_iter().flatten().filter_map(move |entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != extension {
+                return None;
+            }
+
+            let metadata = path.metadata().ok()?;
+            Some((path, metadata.modified().ok()?, metadata.len()))
+        })
+    }
+
+    /// Path of a cache entry.
+    fn entry_path(&self, identity: u64, physical_size: Size) -> PathBuf {
+        let (width, height) = (physical_size.width, physical_size.height);
+        self.directory.join(format!("{identity:016x}-{width}x{height}.{EXTENSION}"))
+    }
+}
+
+/// Background job scaling the background image for a new surface size.
+///
+/// The scaled image is loaded from the cache, or decoded and scaled from its
+/// source and then stored in the cache.
+pub struct ScaleJob {
+    handle: JoinHandle<Option<UnloadedImage>>,
+    physical_size: Size,
+    next_poll: Instant,
+}
+
+impl ScaleJob {
+    /// Start scaling the image configured in the options.
+    ///
+    /// Returns `None` for images which are not read from the filesystem.
+    pub fn spawn(cache: &ImageCache, options: &Options, physical_size: Size) -> Option<Self> {
+        let source = options.image_path()?.to_path_buf();
+        let (cache, options) = (cache.clone(), options.clone());
+        let handle = thread::spawn(move || {
+            let scaling = options.scaling();
+            if let Some(image) = cache.load(&source, scaling, physical_size) {
+                return Some(image);
+            }
+
+            let image = match UnloadedImage::new(&source) {
+                Ok(image) => image.crop(options.crop),
+                Err(err) => {
+                    warn!("Failed to decode image {source:?}: {err}");
+                    return None;
+                },
+            };
+
+            let scaled = scale_to_fit(&image, physical_size, &options);
+            if let Err(err) = cache.store(&source, scaling, physical_size, &scaled) {
+                warn!("Failed to cache image {source:?}: {err}");
+            }
+            Some(scaled.into())
+        });
+
+        Some(Self { handle, physical_size, next_poll: Instant::now() + JOB_POLL_INTERVAL })
+    }
+
+    /// Physical surface size the image is scaled for.
+    pub fn physical_size(&self) -> Size {
+        self.physical_size
+    }
+
+    /// Get the time at which [`Self::poll`] should be called next.
+    pub fn next_poll(&self) -> Instant {
+        self.next_poll
+    }
+
+    /// Check if the job has finished, scheduling the next check otherwise.
+    pub fn poll(&mut self) -> bool {
+        let finished = self.handle.is_finished();
+        if !finished {
+            self.next_poll = Instant::now() + JOB_POLL_INTERVAL;
+        }
+        finished
+    }
+
+    /// Wait for the scaled image.
+    ///
+    /// Returns `None` if the image could not be decoded.
+    pub fn join(self) -> Option<UnloadedImage> {
+        self.handle.join().ok().flatten()
+    }
+}
+
+/// Scale an image down to the size it is drawn at on a surface.
+///
+/// Images which are already smaller are converted without scaling.
+pub fn scale_to_fit(image: &UnloadedImage, physical_size: Size, options: &Options) -> RgbaImage {
+    let image_size = image.size();
+    let (_, size) = options.image_layout(physical_size, image_size);
+    let size = Size::new((size.width.round() as u32).max(1), (size.height.round() as u32).max(1));
+
+    if size.width >= image_size.width || size.height >= image_size.height {
+        image.to_rgba()
+    } else {
+        image.resize(size)
+    }
+}
+
+/// Write a cache entry's header and pixels to a file.
+fn write_entry(path: &Path, image: &RgbaImage) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(MAGIC)?;
+    file.write_all(&image.width().to_le_bytes())?;
+    file.write_all(&image.height().to_le_bytes())?;
+    file.write_all(image.as_raw())
+}
+
+/// Load a cache entry, marking it as recently used.
+fn load_entry(path: &Path) -> Option<UnloadedImage> {
+    let file = File::options().read(true).write(true).open(path).ok()?;
+    let _ = file.set_modified(SystemTime::now());
+
+    // SAFETY: Entries are only ever replaced through renames, never modified.
+    let mmap = unsafe { Mmap::map(&file).ok()? };
+    if mmap.len() < HEADER_SIZE || &mmap[..MAGIC.len()] != MAGIC {
+        return None;
+    }
+
+    let width = u32::from_le_bytes(mmap[MAGIC.len()..MAGIC.len() + 4].try_into().unwrap());
+    let height = u32::from_le_bytes(mmap[MAGIC.len() + 4..HEADER_SIZE].try_into().unwrap());
+    let expected_len = (width as usize).checked_mul(height as usize)?.checked_mul(4)?;
+    if mmap.len() - HEADER_SIZE != expected_len {
+        return None;
+    }
+
+    Some(UnloadedImage::from_mapped(mmap, HEADER_SIZE, Size::new(width, height)))
+}
+
+/// Identify a scaled source image by its path, size and modification time.
+fn identity(source: &Path, scaling: impl Hash) -> Option<u64> {
+    let path = fs::canonicalize(source).ok()?;
+    let metadata = path.metadata().ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+    let mut hasher = Fnv1a::default();
+    hasher.write(path.as_os_str().as_bytes());
+    hasher.write(&metadata.len().to_le_bytes());
+    hasher.write(&modified.as_nanos().to_le_bytes());
+    scaling.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/// FNV-1a hasher.
+///
+/// Unlike the standard library's hasher, this is stable across releases.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xCBF29CE484222325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001B3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+    use crate::cli::Fit;
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn background_scaling() {
+        let directory = env::temp_dir().join(format!("tabula-scale-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let source = directory.join("source.png");
+        RgbaImage::from_pixel(40, 20, Rgba([1, 2, 3, 255])).save(&source).unwrap();
+
+        let cache = ImageCache::with_directory(directory.join("cache"), u64::MAX);
+        let options = Options { image: Some(source.clone()), ..Default::default() };
+        let physical_size = Size::new(10, 10);
+
+        // The image is scaled to cover the surface.
+        let mut job = ScaleJob::spawn(&cache, &options, physical_size).unwrap();
+        while !job.poll() {
+            thread::sleep(JOB_POLL_INTERVAL);
+        }
+        assert_eq!(job.physical_size(), physical_size);
+        assert_eq!(job.join().unwrap().size(), Size::new(20, 10));
+
+        // The scaled copy is stored in the cache.
+        let cached = cache.load(&source, options.scaling(), physical_size).unwrap();
+        assert_eq!(cached.size(), Size::new(20, 10));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn abandoned_tmp_files_removed() {
+        let directory = env::temp_dir().join(format!("tabula-cache-tmp-{}", process::id()));
+        let cache_directory = directory.join("cache");
+        fs::create_dir_all(&cache_directory).unwrap();
+        let source = directory.join("source.png");
+        fs::write(&source, b"source").unwrap();
+
+        let abandoned = cache_directory.join("abandoned.1-0.tmp");
+        fs::write(&abandoned, b"partial").unwrap();
+        let file = File::options().write(true).open(&abandoned).unwrap();
+        file.set_modified(SystemTime::now() - TMP_MAX_AGE * 2).unwrap();
+        let active = cache_directory.join("active.1-1.tmp");
+        fs::write(&active, b"partial").unwrap();
+
+        let cache = ImageCache::with_directory(cache_directory, u64::MAX);
+        let image = RgbaImage::from_pixel(4, 4, Rgba([1, 2, 3, 255]));
+        cache.store(&source, Fit::Cover, Size::new(100, 100), &image).unwrap();
+
+        // Only temporary files which might still be written are kept.
+        assert!(!abandoned.exists());
+        assert!(active.exists());
+        assert_eq!(cache.files(TMP_EXTENSION).count(), 1);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn store_and_evict() {
+        let directory = env::temp_dir().join(format!("tabula-cache-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let source = directory.join("source.png");
+        fs::write(&source, b"source").unwrap();
+
+        // Allow for just two entries of 4x4 pixels.
+        let entry_size = (HEADER_SIZE + 4 * 4 * 4) as u64;
+        let cache = ImageCache::with_directory(directory.join("cache"), entry_size * 2);
+        let image = RgbaImage::from_pixel(4, 4, Rgba([1, 2, 3, 255]));
+
+        assert!(cache.load_latest(&source, Fit::Cover).is_none());
+
+        for width in [100, 200, 300] {
+            cache.store(&source, Fit::Cover, Size::new(width, 100), &image).unwrap();
+
+            let loaded = cache.load(&source, Fit::Cover, Size::new(width, 100)).unwrap();
+            assert_eq!(loaded.to_rgba(), image);
+
+            let (_, size) = cache.load_latest(&source, Fit::Cover).unwrap();
+            assert_eq!(size, Size::new(width, 100));
+        }
+
+        // The oldest entry was evicted.
+        assert!(cache.load(&source, Fit::Cover, Size::new(100, 100)).is_none());
+        assert!(cache.load(&source, Fit::Cover, Size::new(200, 100)).is_some());
+
+        // Images scaled differently are stored separately.
+        assert!(cache.load(&source, Fit::Center, Size::new(200, 100)).is_none());
+
+        // Modifying the source invalidates its entries.
+        fs::write(&source, b"modified source").unwrap();
+        assert!(cache.load(&source, Fit::Cover, Size::new(300, 100)).is_none());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -1,35 +1,888 @@
 //! CLI argument handling.
 
-use std::path::PathBuf;
+use std::fmt::{self, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::os::fd::RawFd;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-use clap::Parser;
+use chrono::format::StrftimeItems;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 
-use crate::geometry::Position;
+use crate::geometry::{self, Orientation, Position, Size};
+use crate::state::SavedState;
 
-#[derive(Parser)]
+#[derive(Parser, Clone)]
 #[clap(version)]
 pub struct Options {
-    /// Background color.
-    #[clap(short, long, value_name = "RRGGBB", default_value = "#000000")]
-    pub color: Rgb,
-    /// Background image.
-    #[clap(short, long, value_name = "PATH")]
+    #[clap(subcommand)]
+    pub subcommand: Option<Subcommands>,
+    /// Background color; use `auto` for the image's average color, or
+    /// `auto-edge` for the average color of its border.
+    #[clap(short, long, value_name = "RRGGBB", default_value = "#000000", global = true)]
+    pub color: Color,
+    /// Procedural background pattern, drawn instead of the plain color.
+    ///
+    /// Patterns are specified as `KIND[,KEY=VALUE...]`, with the kinds
+    /// `checkerboard`, `stripes`, `dots`, `noise` and `sky`. The pattern
+    /// alternates between `--color` and its own color. The keys are:
+    ///  - `color`: second pattern color as `RRGGBB`
+    ///  - `size`: pattern scale in logical pixels
+    ///
+    /// The `sky` pattern is a gradient following the time of day, which
+    /// ignores all keys.
+    #[clap(long, value_name = "SPEC", global = true, verbatim_doc_comment)]
+    pub pattern: Option<Pattern>,
+    /// Background image; use `-` to read it from stdin.
+    #[clap(short, long, value_name = "PATH", global = true)]
     pub image: Option<PathBuf>,
+    /// Read the background image from an inherited file descriptor.
+    #[clap(long, value_name = "FD", conflicts_with = "image", global = true)]
+    pub image_fd: Option<RawFd>,
     /// Relative focus point; overflow is distributed evenly around this
     /// location.
-    #[clap(short, long, value_name = "POINT", default_value = "0.5+0.5")]
+    #[clap(short, long, value_name = "POINT", default_value = "0.5+0.5", global = true)]
     pub focus: Position<f32>,
+    /// Background image for portrait surfaces, replacing `--image`.
+    ///
+    /// Surfaces taller than they are wide are portrait, all others are
+    /// landscape.
+    #[clap(long, value_name = "PATH", global = true)]
+    pub portrait_image: Option<PathBuf>,
+    /// Background image for landscape surfaces, replacing `--image`.
+    #[clap(long, value_name = "PATH", global = true)]
+    pub landscape_image: Option<PathBuf>,
+    /// Focus point for portrait surfaces, replacing `--focus`.
+    #[clap(long, value_name = "POINT", global = true)]
+    pub portrait_focus: Option<Position<f32>>,
+    /// Focus point for landscape surfaces, replacing `--focus`.
+    #[clap(long, value_name = "POINT", global = true)]
+    pub landscape_focus: Option<Position<f32>>,
+    /// Scaling of the background image.
+    #[clap(long, value_enum, default_value = "cover", global = true)]
+    pub fit: Fit,
+    /// Region of the background image to show, as `X,Y,WIDTH,HEIGHT`.
+    ///
+    /// Values are in image pixels, or fractions of the image's size if they
+    /// contain a decimal point, like `0.1,0,0.8,1.0`. The focus point is
+    /// relative to this region.
+    #[clap(long, value_name = "RECT", global = true)]
+    pub crop: Option<Crop>,
+    /// Factor by which the background image is enlarged past its fit.
+    #[clap(long, value_name = "FACTOR", default_value = "1", value_parser = parse_zoom, global = true)]
+    pub zoom: f32,
+    /// Fill of the area around images which do not cover the surface.
+    #[clap(long, value_enum, default_value = "color", global = true)]
+    pub backdrop: Backdrop,
+    /// Additional image drawn on top of the background.
+    ///
+    /// Overlays are specified as `PATH[,KEY=VALUE...]`, with the keys:
+    ///  - `anchor`: `center`, `top`, `bottom`, `left`, `right`, `top-left`,
+    ///    `top-right`, `bottom-left`, `bottom-right` or a relative `X+Y` point
+    ///  - `size`: width in logical pixels, or relative to the surface's width
+    ///    with a `%` suffix; defaults to the image's size in logical pixels
+    ///  - `margin`: distance to the surface's edges in logical pixels
+    ///  - `opacity`: opacity between 0 and 1
+    ///
+    /// This option can be repeated, later overlays are drawn on top.
+    #[clap(long = "overlay", value_name = "SPEC", global = true, verbatim_doc_comment)]
+    pub overlays: Vec<Overlay>,
+    /// Text drawn on top of the background and overlays.
+    ///
+    /// Texts are specified as `TEXT[,KEY=VALUE...]`. The text is a strftime
+    /// format string, so `%H:%M` shows a clock that is updated every minute;
+    /// `{hostname}` is replaced by the system's hostname. The keys are:
+    ///  - `font`: path to a TrueType or OpenType font file
+    ///  - `size`: font size in logical pixels
+    ///  - `color`: text color as `RRGGBB`
+    ///  - `shadow`: drop shadow color as `RRGGBB`
+    ///  - `anchor`, `margin`: placement, like for `--overlay`
+    ///
+    /// Commas inside of the text or paths can be escaped as `\,`.
+    #[clap(long = "text", value_name = "SPEC", global = true, verbatim_doc_comment)]
+    pub texts: Vec<Text>,
+    /// Duration of the image's animation after a resize in milliseconds; `0`
+    /// disables it.
+    ///
+    /// Animations are only used with OpenGL rendering. On battery, they run at
+    /// a reduced frame rate, and they are skipped below
+    /// `--battery-threshold`.
+    #[clap(long, value_name = "MS", default_value = "250", global = true)]
+    pub transition_duration: u64,
+    /// Battery charge in percent below which animations are frozen.
+    ///
+    /// Above this threshold, the sky is redrawn less often and animations run
+    /// at a reduced frame rate while on battery. Animations resume at their
+    /// full rate once a charger is connected.
+    #[clap(
+        long,
+        value_name = "PERCENT",
+        default_value = "20",
+        value_parser = clap::value_parser!(u8).range(..=100),
+        global = true
+    )]
+    pub battery_threshold: u8,
+    /// Render the image without OpenGL, using a shared memory buffer.
+    ///
+    /// This avoids keeping GPU buffers alive for static images.
+    #[clap(long)]
+    pub shm: bool,
+    /// Recreate the wallpaper when the compositor closes it, instead of
+    /// exiting.
+    ///
+    /// Compositors close the wallpaper when its output is unplugged; it is
+    /// shown again once an output is available.
+    #[clap(long)]
+    pub reopen: bool,
+    /// Layer shell layer of the wallpaper surface.
+    #[clap(long, value_enum, default_value = "background")]
+    pub layer: Layer,
+    /// Layer shell namespace, to identify the surface in compositor rules.
+    #[clap(long, value_name = "NAME", default_value = "wallpaper")]
+    pub namespace: String,
+    /// Screen edges the surface is attached to, as a comma-separated list of
+    /// `top`, `bottom`, `left` and `right`, or `all`.
+    ///
+    /// Surfaces attached to two opposite edges stretch between them, unless
+    /// their size is set with `--surface-size`.
+    #[clap(long, value_name = "EDGES", default_value = "all")]
+    pub anchor: Anchors,
+    /// Logical surface size as `WIDTHxHEIGHT`; `0` stretches the surface
+    /// between its anchored edges.
+    #[clap(long, value_name = "WIDTHxHEIGHT", default_value = "0x0")]
+    pub surface_size: Size,
+    /// Distance to the anchored edges in logical pixels, as
+    /// `TOP,RIGHT,BOTTOM,LEFT` or a single value for all edges.
+    #[clap(long, value_name = "MARGIN", default_value = "0", allow_hyphen_values = true)]
+    pub margin: Margin,
+    /// Size of the area reserved for the surface at its anchored edge.
+    ///
+    /// With `-1`, the surface extends below other surfaces' exclusive zones;
+    /// with `0`, it is moved to avoid them.
+    #[clap(long, value_name = "PIXELS", default_value = "-1", allow_hyphen_values = true)]
+    pub exclusive_zone: i32,
+    /// Size limit of the scaled image cache in MiB; `0` disables it.
+    ///
+    /// Decoded images are cached at the surface's resolution in
+    /// `$XDG_CACHE_HOME/tabula`, to skip decoding them on startup.
+    #[clap(long, value_name = "MIB", default_value = "128")]
+    pub cache_size: u64,
+    /// Write the image's color palette to this directory.
+    ///
+    /// The palette is written as `palette.json`, `palette.sh` and
+    /// `palette.css`, and updated whenever the image is reloaded.
+    #[clap(long, value_name = "DIR")]
+    pub palette_dir: Option<PathBuf>,
+    /// Resume the last image, color and focus, unless passed explicitly.
+    ///
+    /// The state is saved separately for each `--namespace`.
+    #[clap(long)]
+    pub restore: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        // Use the defaults of the CLI parser.
+        Self::parse_from(["tabula"])
+    }
+}
+
+impl Options {
+    /// Parse the process's CLI arguments.
+    ///
+    /// With `--restore`, the last saved state is used for all options which
+    /// were not passed explicitly.
+    pub fn from_args() -> Self {
+        let matches = Self::command().get_matches();
+        let mut options = Self::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+        if let Err(err) = options.validate() {
+            Self::command().error(ErrorKind::ArgumentConflict, err).exit();
+        }
+
+        let state = options.restore.then(|| SavedState::load(&options.namespace)).flatten();
+        if let Some(state) = state {
+            let is_explicit = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+            state.restore(&mut options, is_explicit);
+        }
+
+        options
+    }
+
+    /// Reject options which are out of range, or layer shell surface options
+    /// which contradict each other.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.battery_threshold > 100 {
+            return Err("`--battery-threshold` must not exceed 100 percent".into());
+        }
+
+        let anchor = self.anchor;
+        if self.namespace.is_empty() {
+            return Err("`--namespace` must not be empty".into());
+        }
+        if self.surface_size.width == 0 && !(anchor.left && anchor.right) {
+            return Err("surfaces without left and right anchors require a width".into());
+        }
+        if self.surface_size.height == 0 && !(anchor.top && anchor.bottom) {
+            return Err("surfaces without top and bottom anchors require a height".into());
+        }
+        if self.exclusive_zone > 0 && !anchor.has_exclusive_edge() {
+            return Err("a positive `--exclusive-zone` requires anchoring to a single edge, \
+                        optionally stretched between its two adjacent edges"
+                .into());
+        }
+        Ok(())
+    }
+
+    /// Path of the background image file.
+    ///
+    /// This is `None` for images that are not read from the filesystem.
+    pub fn image_path(&self) -> Option<&Path> {
+        self.image.as_deref().filter(|path| *path != Path::new("-"))
+    }
+
+    /// Paths of all background image files, including orientation-specific
+    /// ones.
+    pub fn image_paths(&self) -> impl Iterator<Item = &Path> {
+        let oriented = [&self.portrait_image, &self.landscape_image].into_iter().flatten();
+        self.image_path().into_iter().chain(oriented.map(PathBuf::as_path))
+    }
+
+    /// Check if the background image depends on the surface's orientation.
+    pub fn has_oriented_images(&self) -> bool {
+        self.portrait_image.is_some() || self.landscape_image.is_some()
+    }
+
+    /// Get the options for a surface orientation.
+    ///
+    /// Orientation-specific images and focus points replace the defaults.
+    /// Without any default image, the other orientation's image is used.
+    pub fn oriented(&self, orientation: Orientation) -> Self {
+        let (image, other_image, focus) = match orientation {
+            Orientation::Portrait => {
+                (&self.portrait_image, &self.landscape_image, self.portrait_focus)
+            },
+            Orientation::Landscape => {
+                (&self.landscape_image, &self.portrait_image, self.landscape_focus)
+            },
+        };
+
+        let mut options = self.clone();
+        let has_default_image = self.image.is_some() || self.image_fd.is_some();
+        let image = image.as_ref().or(other_image.as_ref().filter(|_| !has_default_image));
+        if let Some(image) = image {
+            options.image = Some(image.clone());
+            options.image_fd = None;
+        }
+        if let Some(focus) = focus {
+            options.focus = focus;
+        }
+
+        options
+    }
+
+    /// Get the background image's physical position and size on a surface.
+    ///
+    /// The image is laid out according to `fit` and enlarged by `zoom`, with
+    /// its overflow distributed around the focus point.
+    pub fn image_layout(
+        &self,
+        physical_size: Size,
+        image_size: Size,
+    ) -> (Position<f32>, Size<f32>) {
+        let (position, size) = self.fit.layout(physical_size, image_size, self.focus);
+        if self.zoom == 1. {
+            return (position, size);
+        }
+
+        let size = Size::new(size.width * self.zoom, size.height * self.zoom);
+        (geometry::anchor(physical_size.into(), size, self.focus, 0.), size)
+    }
+
+    /// Identify how the background image is scaled for a surface.
+    pub fn scaling(&self) -> (Fit, Option<Crop>, u32) {
+        (self.fit, self.crop, self.zoom.to_bits())
+    }
+}
+
+#[derive(Subcommand, Clone)]
+pub enum Subcommands {
+    /// Render the wallpaper to a PNG file, without a Wayland connection.
+    Render(RenderOptions),
+    /// Print the dominant and accent colors of an image.
+    Palette(PaletteOptions),
+}
+
+#[derive(Args, Clone)]
+pub struct RenderOptions {
+    /// Logical surface size.
+    #[clap(short, long, value_name = "WIDTHxHEIGHT")]
+    pub size: Size,
+    /// Surface scale factor.
+    #[clap(long, value_name = "SCALE", default_value = "1", value_parser = parse_scale)]
+    pub scale: f64,
+    /// Output PNG file.
+    #[clap(short, long, value_name = "PATH")]
+    pub output: PathBuf,
+}
+
+#[derive(Args, Clone)]
+pub struct PaletteOptions {
+    /// Image to extract the colors from.
+    #[clap(value_name = "IMAGE")]
+    pub path: PathBuf,
+    /// Output format.
+    #[clap(long, value_enum, default_value = "json")]
+    pub format: PaletteFormat,
+    /// Maximum number of colors.
+    #[clap(short = 'n', long, default_value = "8", value_parser = clap::value_parser!(u8).range(1..))]
+    pub colors: u8,
+}
+
+/// Color palette output format.
+#[derive(ValueEnum, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PaletteFormat {
+    /// JSON object.
+    Json,
+    /// Shell variable assignments.
+    Shell,
+    /// CSS custom properties.
+    Css,
+}
+
+/// Parse a positive scale factor.
+fn parse_scale(s: &str) -> Result<f64, String> {
+    match f64::from_str(s) {
+        Ok(scale) if scale > 0. && scale.is_finite() => Ok(scale),
+        _ => Err(format!("invalid scale factor: {s:?}")),
+    }
+}
+
+/// Parse a zoom factor of at least one.
+fn parse_zoom(s: &str) -> Result<f32, String> {
+    parse_float(s, 1., f32::MAX)
+}
+
+/// Image layered on top of the background.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Overlay {
+    pub path: PathBuf,
+    pub anchor: Position<f32>,
+    pub size: Option<Length>,
+    pub margin: f32,
+    pub opacity: f32,
+}
+
+impl Overlay {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            anchor: Position::new(0.5, 0.5),
+            size: Default::default(),
+            margin: Default::default(),
+            opacity: 1.,
+        }
+    }
+
+    /// Get the overlay's physical position and size on a surface.
+    pub fn layout(
+        &self,
+        physical_size: Size,
+        scale: f64,
+        image_size: Size,
+    ) -> (Position<f32>, Size<f32>) {
+        let scale = scale as f32;
+        let physical_size: Size<f32> = physical_size.into();
+        let image_size: Size<f32> = image_size.into();
+
+        // Scale image to the desired width, keeping its aspect ratio.
+        let width = match self.size {
+            Some(Length::Logical(width)) => width * scale,
+            Some(Length::Relative(fraction)) => physical_size.width * fraction,
+            None => image_size.width * scale,
+        };
+        let size = Size::new(width, image_size.height * width / image_size.width);
+
+        let position = geometry::anchor(physical_size, size, self.anchor, self.margin * scale);
+
+        (position, size)
+    }
+}
+
+/// CLI parser.
+impl FromStr for Overlay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut properties = split_spec(s).into_iter();
+        let path = properties.next().filter(|path| !path.is_empty()).ok_or("missing path")?;
+        let mut overlay = Overlay::new(path);
+
+        for property in properties {
+            let (key, value) =
+                property.split_once('=').ok_or(format!("expected KEY=VALUE: {property:?}"))?;
+            match key {
+                "anchor" => overlay.anchor = parse_anchor(value)?,
+                "size" => overlay.size = Some(value.parse()?),
+                "margin" => overlay.margin = parse_float(value, 0., f32::MAX)?,
+                "opacity" => overlay.opacity = parse_float(value, 0., 1.)?,
+                _ => return Err(format!("unknown overlay property: {key:?}")),
+            }
+        }
+
+        Ok(overlay)
+    }
+}
+
+/// Text layered on top of the background.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Text {
+    pub format: String,
+    pub font: Option<PathBuf>,
+    pub size: f32,
+    pub color: Rgb,
+    pub shadow: Option<Rgb>,
+    pub anchor: Position<f32>,
+    pub margin: f32,
+}
+
+impl Text {
+    pub fn new(format: impl Into<String>) -> Self {
+        Self {
+            format: format.into(),
+            color: Rgb { r: 255, g: 255, b: 255 },
+            anchor: Position::new(0.5, 0.5),
+            size: 24.,
+            shadow: Default::default(),
+            margin: Default::default(),
+            font: Default::default(),
+        }
+    }
+
+    /// Get the physical position of the rasterized text on a surface.
+    pub fn layout(&self, physical_size: Size, scale: f64, image_size: Size) -> Position<f32> {
+        let margin = self.margin * scale as f32;
+        geometry::anchor(physical_size.into(), image_size.into(), self.anchor, margin)
+    }
+}
+
+/// CLI parser.
+impl FromStr for Text {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut properties = split_spec(s).into_iter();
+        let format = properties.next().filter(|format| !format.is_empty()).ok_or("missing text")?;
+
+        // Reject invalid format strings early, since formatting them would panic.
+        if StrftimeItems::new(&format).parse().is_err() {
+            return Err(format!("invalid time format: {format:?}"));
+        }
+
+        let mut text = Text::new(format);
+        for property in properties {
+            let (key, value) =
+                property.split_once('=').ok_or(format!("expected KEY=VALUE: {property:?}"))?;
+            match key {
+                "font" => text.font = Some(value.into()),
+                "size" => text.size = parse_float(value, f32::MIN_POSITIVE, f32::MAX)?,
+                "color" => text.color = value.parse()?,
+                "shadow" => text.shadow = Some(value.parse()?),
+                "anchor" => text.anchor = parse_anchor(value)?,
+                "margin" => text.margin = parse_float(value, 0., f32::MAX)?,
+                _ => return Err(format!("unknown text property: {key:?}")),
+            }
+        }
+
+        Ok(text)
+    }
+}
+
+/// Procedurally generated background.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub color: Rgb,
+    pub size: f32,
+}
+
+impl Pattern {
+    pub fn new(kind: PatternKind) -> Self {
+        Self { kind, color: Rgb { r: 128, g: 128, b: 128 }, size: 32. }
+    }
+}
+
+/// CLI parser.
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut properties = split_spec(s).into_iter();
+        let kind = match properties.next().as_deref() {
+            Some("checkerboard") => PatternKind::Checkerboard,
+            Some("stripes") => PatternKind::Stripes,
+            Some("dots") => PatternKind::Dots,
+            Some("noise") => PatternKind::Noise,
+            Some("sky") => PatternKind::Sky,
+            kind => return Err(format!("unknown pattern: {:?}", kind.unwrap_or_default())),
+        };
+
+        let mut pattern = Pattern::new(kind);
+        for property in properties {
+            let (key, value) =
+                property.split_once('=').ok_or(format!("expected KEY=VALUE: {property:?}"))?;
+            match key {
+                "color" => pattern.color = value.parse()?,
+                "size" => pattern.size = parse_float(value, f32::MIN_POSITIVE, f32::MAX)?,
+                _ => return Err(format!("unknown pattern property: {key:?}")),
+            }
+        }
+
+        Ok(pattern)
+    }
+}
+
+/// Procedural pattern type.
+///
+/// The discriminants are used to select the pattern in the shader.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PatternKind {
+    Checkerboard = 0,
+    Stripes = 1,
+    Dots = 2,
+    Noise = 3,
+    Sky = 4,
+}
+
+/// Split a `VALUE[,KEY=VALUE...]` spec, allowing commas to be escaped as `\,`.
+fn split_spec(s: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&',') => {
+                chars.next();
+                parts.last_mut().unwrap().push(',');
+            },
+            ',' => parts.push(String::new()),
+            c => parts.last_mut().unwrap().push(c),
+        }
+    }
+
+    parts
+}
+
+/// Parse a named or relative anchor point.
+fn parse_anchor(s: &str) -> Result<Position<f32>, String> {
+    let (x, y) = match s {
+        "center" => (0.5, 0.5),
+        "top" => (0.5, 0.),
+        "bottom" => (0.5, 1.),
+        "left" => (0., 0.5),
+        "right" => (1., 0.5),
+        "top-left" => (0., 0.),
+        "top-right" => (1., 0.),
+        "bottom-left" => (0., 1.),
+        "bottom-right" => (1., 1.),
+        _ => return s.parse(),
+    };
+    Ok(Position::new(x, y))
+}
+
+/// Parse a float within an inclusive range.
+fn parse_float(s: &str, min: f32, max: f32) -> Result<f32, String> {
+    match f32::from_str(s) {
+        Ok(value) if (min..=max).contains(&value) => Ok(value),
+        _ => Err(format!("expected number between {min} and {max}: {s:?}")),
+    }
+}
+
+/// Absolute or relative length.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Length {
+    /// Length in logical pixels.
+    Logical(f32),
+    /// Fraction of the available space.
+    Relative(f32),
+}
+
+/// CLI parser.
+impl FromStr for Length {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_suffix('%') {
+            Some(percent) => Ok(Self::Relative(parse_float(percent, 0., f32::MAX)? / 100.)),
+            None => Ok(Self::Logical(parse_float(s, 0., f32::MAX)?)),
+        }
+    }
+}
+
+/// Region of an image.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Crop {
+    pub x: CropLength,
+    pub y: CropLength,
+    pub width: CropLength,
+    pub height: CropLength,
+}
+
+impl Crop {
+    /// Get the region's pixel bounds, clamped to the image.
+    ///
+    /// The region is always at least one pixel wide and tall.
+    pub fn rect(&self, image_size: Size) -> (Position<u32>, Size) {
+        let max_x = image_size.width.saturating_sub(1);
+        let max_y = image_size.height.saturating_sub(1);
+        let x = self.x.resolve(image_size.width).min(max_x);
+        let y = self.y.resolve(image_size.height).min(max_y);
+        let width = self.width.resolve(image_size.width).clamp(1, image_size.width - x);
+        let height = self.height.resolve(image_size.height).clamp(1, image_size.height - y);
+        (Position::new(x, y), Size::new(width, height))
+    }
+}
+
+/// CLI parser.
+impl FromStr for Crop {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s.split(',').map(CropLength::from_str).collect::<Result<Vec<_>, _>>()?;
+        match values[..] {
+            [x, y, width, height] => Ok(Self { x, y, width, height }),
+            _ => Err(format!("expected `X,Y,WIDTH,HEIGHT`: {s:?}")),
+        }
+    }
+}
+
+impl Hash for Crop {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for length in [self.x, self.y, self.width, self.height] {
+            match length {
+                CropLength::Pixels(pixels) => (0u8, pixels).hash(state),
+                CropLength::Fraction(fraction) => (1u8, fraction.to_bits()).hash(state),
+            }
+        }
+    }
+}
+
+/// Length in image pixels or relative to the image's size.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CropLength {
+    Pixels(u32),
+    Fraction(f32),
+}
+
+impl CropLength {
+    /// Convert the length to pixels of an image's dimension.
+    fn resolve(self, image_length: u32) -> u32 {
+        match self {
+            Self::Pixels(pixels) => pixels,
+            Self::Fraction(fraction) => (image_length as f32 * fraction).round() as u32,
+        }
+    }
+}
+
+/// CLI parser.
+impl FromStr for CropLength {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains('.') {
+            return Ok(Self::Fraction(parse_float(s, 0., 1.)?));
+        }
+
+        let pixels = u32::from_str(s).map_err(|_| format!("invalid pixel count: {s:?}"))?;
+        Ok(Self::Pixels(pixels))
+    }
+}
+
+/// Image scaling mode.
+#[derive(ValueEnum, Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Fit {
+    /// Scale the image to cover the surface, cropping its overflow.
+    Cover,
+    /// Scale the image to fit inside the surface, leaving space around it.
+    Contain,
+    /// Show the image without scaling, with one pixel per physical pixel.
+    Center,
+}
+
+impl Fit {
+    /// Get the image's physical position and size on a surface.
+    ///
+    /// The image is aligned to the `focus` point, which also distributes
+    /// any overflow.
+    pub fn layout(
+        self,
+        physical_size: Size,
+        image_size: Size,
+        focus: Position<f32>,
+    ) -> (Position<f32>, Size<f32>) {
+        let physical_size: Size<f32> = physical_size.into();
+        let image_size: Size<f32> = image_size.into();
+
+        let size = match self {
+            Self::Cover => return geometry::cover(physical_size, image_size, focus),
+            Self::Contain => {
+                let width_ratio = physical_size.width / image_size.width;
+                let height_ratio = physical_size.height / image_size.height;
+                let ratio = width_ratio.min(height_ratio);
+                Size::new(image_size.width * ratio, image_size.height * ratio)
+            },
+            Self::Center => image_size,
+        };
+
+        (geometry::anchor(physical_size, size, focus, 0.), size)
+    }
+}
+
+/// Fill of the area not covered by the image.
+#[derive(ValueEnum, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Backdrop {
+    /// Background color or pattern.
+    Color,
+    /// Scaled-up, blurred and dimmed copy of the image.
+    Blur,
+}
+
+/// Layer shell layer, from bottom to top.
+#[derive(ValueEnum, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Layer {
+    Background,
+    Bottom,
+    Top,
+    Overlay,
+}
+
+/// Screen edges a surface is attached to.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Anchors {
+    pub top: bool,
+    pub bottom: bool,
+    pub left: bool,
+    pub right: bool,
+}
+
+impl Anchors {
+    /// All screen edges.
+    pub const ALL: Self = Self { top: true, bottom: true, left: true, right: true };
+
+    /// Check if the anchors determine an edge for an exclusive zone.
+    ///
+    /// This requires a single edge, or an edge and both of its adjacent edges.
+    fn has_exclusive_edge(self) -> bool {
+        let horizontal = self.left as u8 + self.right as u8;
+        let vertical = self.top as u8 + self.bottom as u8;
+        matches!((horizontal, vertical), (1, 0 | 2) | (0 | 2, 1))
+    }
+}
+
+/// CLI parser.
+impl FromStr for Anchors {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "all" {
+            return Ok(Self::ALL);
+        }
+
+        let mut anchors = Self { top: false, bottom: false, left: false, right: false };
+        for edge in s.split(',') {
+            match edge {
+                "top" => anchors.top = true,
+                "bottom" => anchors.bottom = true,
+                "left" => anchors.left = true,
+                "right" => anchors.right = true,
+                _ => return Err(format!("invalid edge: {edge:?}")),
+            }
+        }
+        Ok(anchors)
+    }
+}
+
+/// Distance to a surface's anchored edges in logical pixels.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct Margin {
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+    pub left: i32,
+}
+
+/// CLI parser.
+impl FromStr for Margin {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|value| i32::from_str(value).map_err(|_| format!("invalid margin: {value:?}")))
+            .collect::<Result<Vec<_>, _>>()?;
+        match values[..] {
+            [all] => Ok(Self { top: all, right: all, bottom: all, left: all }),
+            [top, right, bottom, left] => Ok(Self { top, right, bottom, left }),
+            _ => Err(format!("expected `TOP,RIGHT,BOTTOM,LEFT` or a single value: {s:?}")),
+        }
+    }
+}
+
+/// Background color.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Color {
+    Rgb(Rgb),
+    /// Average color of the image.
+    Auto,
+    /// Average color of the image's border pixels.
+    AutoEdge,
+}
+
+impl From<Rgb> for Color {
+    fn from(rgb: Rgb) -> Self {
+        Self::Rgb(rgb)
+    }
+}
+
+impl Display for Color {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rgb(rgb) => write!(f, "{rgb}"),
+            Self::Auto => write!(f, "auto"),
+            Self::AutoEdge => write!(f, "auto-edge"),
+        }
+    }
+}
+
+/// CLI parser.
+impl FromStr for Color {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "auto-edge" => Ok(Self::AutoEdge),
+            _ => Ok(Self::Rgb(s.parse()?)),
+        }
+    }
 }
 
 /// RGB color.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
 pub struct Rgb {
     pub r: u8,
     pub g: u8,
     pub b: u8,
 }
 
+/// Format as `#RRGGBB` hex color.
+impl Display for Rgb {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
 impl FromStr for Rgb {
     type Err = &'static str;
 
@@ -55,3 +908,213 @@ impl FromStr for Rgb {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::iter;
+
+    use super::*;
+
+    #[test]
+    fn parse_overlay() {
+        let overlay: Overlay = "logo.png".parse().unwrap();
+        assert_eq!(overlay, Overlay::new("logo.png"));
+
+        let overlay: Overlay =
+            "logo.png,anchor=bottom-right,size=20%,margin=16,opacity=0.5".parse().unwrap();
+        assert_eq!(overlay.anchor, Position::new(1., 1.));
+        assert_eq!(overlay.size, Some(Length::Relative(0.2)));
+        assert_eq!(overlay.margin, 16.);
+        assert_eq!(overlay.opacity, 0.5);
+
+        let overlay: Overlay = "logo.png,anchor=0.25+0.75,size=64".parse().unwrap();
+        assert_eq!(overlay.anchor, Position::new(0.25, 0.75));
+        assert_eq!(overlay.size, Some(Length::Logical(64.)));
+
+        assert!("logo.png,opacity=2".parse::<Overlay>().is_err());
+        assert!("logo.png,scale=2".parse::<Overlay>().is_err());
+        assert!(",size=64".parse::<Overlay>().is_err());
+    }
+
+    #[test]
+    fn parse_text() {
+        let text: Text = "%H:%M".parse().unwrap();
+        assert_eq!(text, Text::new("%H:%M"));
+
+        let text: Text =
+            r"Hello\, {hostname},size=12,color=ff0000,shadow=000000,anchor=top,font=a.ttf"
+                .parse()
+                .unwrap();
+        assert_eq!(text.format, "Hello, {hostname}");
+        assert_eq!(text.size, 12.);
+        assert_eq!(text.color, Rgb { r: 255, g: 0, b: 0 });
+        assert_eq!(text.shadow, Some(Rgb { r: 0, g: 0, b: 0 }));
+        assert_eq!(text.anchor, Position::new(0.5, 0.));
+        assert_eq!(text.font, Some(PathBuf::from("a.ttf")));
+
+        assert!("%Q".parse::<Text>().is_err());
+        assert!("clock,size=0".parse::<Text>().is_err());
+    }
+
+    #[test]
+    fn parse_pattern() {
+        let pattern: Pattern = "dots,color=ff0000,size=8.5".parse().unwrap();
+        assert_eq!(pattern.kind, PatternKind::Dots);
+        assert_eq!(pattern.color, Rgb { r: 255, g: 0, b: 0 });
+        assert_eq!(pattern.size, 8.5);
+
+        assert_eq!("sky".parse(), Ok(Pattern::new(PatternKind::Sky)));
+        assert!("waves".parse::<Pattern>().is_err());
+        assert!("stripes,size=0".parse::<Pattern>().is_err());
+    }
+
+    #[test]
+    fn overlay_layout() {
+        let mut overlay = Overlay::new("logo.png");
+        overlay.anchor = Position::new(1., 1.);
+        overlay.size = Some(Length::Logical(20.));
+        overlay.margin = 5.;
+
+        let (position, size) = overlay.layout(Size::new(200, 400), 2., Size::new(40, 10));
+        assert_eq!(size, Size::new(40., 10.));
+        assert_eq!(position, Position::new(150., 380.));
+
+        overlay.size = Some(Length::Relative(0.5));
+        let (_, size) = overlay.layout(Size::new(200, 400), 2., Size::new(40, 10));
+        assert_eq!(size, Size::new(100., 25.));
+    }
+
+    #[test]
+    fn parse_color() {
+        let red = Rgb { r: 255, g: 0, b: 0 };
+        assert_eq!("#ff0000".parse(), Ok(Color::Rgb(red)));
+        assert_eq!("auto".parse(), Ok(Color::Auto));
+        assert_eq!("auto-edge".parse(), Ok(Color::AutoEdge));
+        assert!("automatic".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn fit_layout() {
+        let physical_size = Size::new(200, 100);
+        let image_size = Size::new(50, 50);
+        let focus = Position::new(0.5, 0.5);
+
+        let (position, size) = Fit::Cover.layout(physical_size, image_size, focus);
+        assert_eq!((position, size), (Position::new(0., -50.), Size::new(200., 200.)));
+
+        let (position, size) = Fit::Contain.layout(physical_size, image_size, focus);
+        assert_eq!((position, size), (Position::new(50., 0.), Size::new(100., 100.)));
+
+        let focus = Position::new(0., 1.);
+        let (position, size) = Fit::Center.layout(physical_size, image_size, focus);
+        assert_eq!((position, size), (Position::new(0., 50.), Size::new(50., 50.)));
+    }
+
+    #[test]
+    fn crop_rect() {
+        let crop: Crop = "10,0.25,0.5,100".parse().unwrap();
+        assert_eq!(crop.x, CropLength::Pixels(10));
+        assert_eq!(crop.y, CropLength::Fraction(0.25));
+
+        // The region is clamped to the image.
+        let (position, size) = crop.rect(Size::new(40, 80));
+        assert_eq!((position, size), (Position::new(10, 20), Size::new(20, 60)));
+
+        let crop: Crop = "50,0,0,0".parse().unwrap();
+        let (position, size) = crop.rect(Size::new(40, 80));
+        assert_eq!((position, size), (Position::new(39, 0), Size::new(1, 1)));
+
+        assert!("0,0,10".parse::<Crop>().is_err());
+        assert!("0,0,1.5,1".parse::<Crop>().is_err());
+        assert!("-1,0,10,10".parse::<Crop>().is_err());
+    }
+
+    #[test]
+    fn zoom_layout() {
+        let options = Options::parse_from(["tabula", "--zoom", "2", "-f", "1+0"]);
+        let (position, size) = options.image_layout(Size::new(100, 100), Size::new(50, 100));
+        assert_eq!((position, size), (Position::new(-100., 0.), Size::new(200., 400.)));
+
+        assert!(Options::try_parse_from(["tabula", "--zoom", "0.5"]).is_err());
+    }
+
+    #[test]
+    fn layer_surface_validation() {
+        let validate = |args: &[&str]| {
+            let args = iter::once("tabula").chain(args.iter().copied());
+            Options::parse_from(args).validate()
+        };
+
+        assert_eq!(validate(&[]), Ok(()));
+        assert_eq!(validate(&["--anchor", "top,left,right", "--surface-size", "0x32"]), Ok(()));
+        assert_eq!(
+            validate(&["--anchor", "top", "--exclusive-zone", "32", "--surface-size", "8x8"]),
+            Ok(())
+        );
+        assert_eq!(validate(&["--anchor", "bottom,right", "--surface-size", "64x64"]), Ok(()));
+
+        // Unanchored axes require a size.
+        assert!(validate(&["--anchor", "top,left,right"]).is_err());
+        assert!(validate(&["--anchor", "left,top,bottom", "--surface-size", "0x32"]).is_err());
+
+        // Exclusive zones require a single edge.
+        assert!(validate(&["--exclusive-zone", "32"]).is_err());
+        assert!(
+            validate(&["--anchor", "top,left", "--surface-size", "8x8", "--exclusive-zone", "8"])
+                .is_err()
+        );
+
+        assert!(validate(&["--namespace", ""]).is_err());
+    }
+
+    #[test]
+    fn battery_threshold_range() {
+        let options = Options::parse_from(["tabula", "--battery-threshold", "100"]);
+        assert_eq!(options.battery_threshold, 100);
+        assert!(Options::try_parse_from(["tabula", "--battery-threshold", "101"]).is_err());
+
+        // Options set without the CLI are checked too.
+        let options = Options { battery_threshold: 101, ..Options::default() };
+        assert!(options.validate().is_err());
+    }
+
+    #[test]
+    fn parse_margin() {
+        let margin: Margin = "-4".parse().unwrap();
+        assert_eq!(margin, Margin { top: -4, right: -4, bottom: -4, left: -4 });
+        let margin: Margin = "1,2,3,4".parse().unwrap();
+        assert_eq!(margin, Margin { top: 1, right: 2, bottom: 3, left: 4 });
+        assert!("1,2".parse::<Margin>().is_err());
+
+        let anchors: Anchors = "bottom,left".parse().unwrap();
+        assert_eq!(anchors, Anchors { top: false, bottom: true, left: true, right: false });
+        assert!("top,middle".parse::<Anchors>().is_err());
+    }
+
+    #[test]
+    fn oriented_options() {
+        let options = Options::parse_from([
+            "tabula",
+            "-i",
+            "default.png",
+            "--portrait-image",
+            "portrait.png",
+            "--landscape-focus",
+            "0+1",
+        ]);
+
+        let portrait = options.oriented(Orientation::Portrait);
+        assert_eq!(portrait.image, Some(PathBuf::from("portrait.png")));
+        assert_eq!(portrait.focus, Position::new(0.5, 0.5));
+
+        let landscape = options.oriented(Orientation::Landscape);
+        assert_eq!(landscape.image, Some(PathBuf::from("default.png")));
+        assert_eq!(landscape.focus, Position::new(0., 1.));
+
+        // Without a default, the other orientation's image is used.
+        let options = Options::parse_from(["tabula", "--portrait-image", "portrait.png"]);
+        let landscape = options.oriented(Orientation::Landscape);
+        assert_eq!(landscape.image, Some(PathBuf::from("portrait.png")));
+        assert_eq!(options.image_paths().count(), 1);
+    }
+}
@@ -3,6 +3,8 @@
 use std::ops::Mul;
 use std::str::FromStr;
 
+use smithay_client_toolkit::reexports::client::protocol::wl_output::Transform;
+
 /// 2D object position.
 #[derive(PartialEq, Eq, Copy, Clone, Default, Debug)]
 pub struct Position<T = i32> {
@@ -56,6 +58,22 @@ impl<T> From<(T, T)> for Size<T> {
     }
 }
 
+/// CLI parser.
+impl<T> FromStr for Size<T>
+where
+    T: FromStr,
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (width, height) =
+            s.split_once('x').ok_or("width and height must be separated by `x`".to_string())?;
+        let width = T::from_str(width).map_err(|_| format!("invalid width: {width:?}"))?;
+        let height = T::from_str(height).map_err(|_| format!("invalid height: {height:?}"))?;
+        Ok(Size { width, height })
+    }
+}
+
 impl From<Size> for Size<f32> {
     fn from(size: Size) -> Self {
         Self { width: size.width as f32, height: size.height as f32 }
@@ -71,3 +89,133 @@ impl Mul<f64> for Size {
         self
     }
 }
+
+/// Surface orientation.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Orientation {
+    /// Taller than wide.
+    Portrait,
+    /// Wider than tall, or square.
+    Landscape,
+}
+
+impl Orientation {
+    /// Get the orientation of a size.
+    pub fn of(size: Size) -> Self {
+        if size.height > size.width { Self::Portrait } else { Self::Landscape }
+    }
+}
+
+/// Get the size of a buffer for a surface with a buffer transform.
+///
+/// Rotations by 90 or 270 degrees swap the buffer's width and height.
+pub fn transform_size(transform: Transform, size: Size) -> Size {
+    match transform {
+        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => {
+            Size::new(size.height, size.width)
+        },
+        _ => size,
+    }
+}
+
+/// Scale `source` to cover `target`, distributing the overflow around `focus`.
+///
+/// The returned position and size are in `target`'s coordinate space.
+pub fn cover(
+    target: Size<f32>,
+    source: Size<f32>,
+    focus: Position<f32>,
+) -> (Position<f32>, Size<f32>) {
+    let width_ratio = target.width / source.width;
+    let height_ratio = target.height / source.height;
+    if width_ratio < height_ratio {
+        let width = source.width * height_ratio;
+        let x = (target.width - width) * focus.x;
+        (Position::new(x, 0.), Size::new(width, target.height))
+    } else {
+        let height = source.height * width_ratio;
+        let y = (target.height - height) * focus.y;
+        (Position::new(0., y), Size::new(target.width, height))
+    }
+}
+
+/// Align `source` inside `target`, keeping a `margin` to its edges.
+///
+/// The `anchor` is relative to the available space, so `0+0` is the top-left
+/// and `1+1` the bottom-right corner.
+pub fn anchor(
+    target: Size<f32>,
+    source: Size<f32>,
+    anchor: Position<f32>,
+    margin: f32,
+) -> Position<f32> {
+    let x = margin + (target.width - source.width - 2. * margin) * anchor.x;
+    let y = margin + (target.height - source.height - 2. * margin) * anchor.y;
+    Position::new(x, y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anchor_corners() {
+        let target = Size::new(100., 200.);
+        let source = Size::new(20., 10.);
+
+        let position = anchor(target, source, Position::new(0., 0.), 5.);
+        assert_eq!(position, Position::new(5., 5.));
+
+        let position = anchor(target, source, Position::new(1., 1.), 5.);
+        assert_eq!(position, Position::new(75., 185.));
+
+        let position = anchor(target, source, Position::new(0.5, 0.5), 5.);
+        assert_eq!(position, Position::new(40., 95.));
+    }
+
+    #[test]
+    fn cover_wide_source() {
+        let target = Size::new(100., 200.);
+        let source = Size::new(400., 200.);
+
+        let (position, size) = cover(target, source, Position::new(0.5, 0.5));
+        assert_eq!(size, Size::new(400., 200.));
+        assert_eq!(position, Position::new(-150., 0.));
+
+        let (position, _) = cover(target, source, Position::new(0., 1.));
+        assert_eq!(position, Position::new(0., 0.));
+
+        let (position, _) = cover(target, source, Position::new(1., 0.));
+        assert_eq!(position, Position::new(-300., 0.));
+    }
+
+    #[test]
+    fn cover_tall_source() {
+        let target = Size::new(300., 100.);
+        let source = Size::new(30., 40.);
+
+        let (position, size) = cover(target, source, Position::new(0.5, 0.5));
+        assert_eq!(size, Size::new(300., 400.));
+        assert_eq!(position, Position::new(0., -150.));
+
+        let (position, _) = cover(target, source, Position::new(1., 0.25));
+        assert_eq!(position, Position::new(0., -75.));
+    }
+
+    #[test]
+    fn cover_matching_ratio() {
+        let target = Size::new(100., 50.);
+        let source = Size::new(10., 5.);
+
+        let (position, size) = cover(target, source, Position::new(0.9, 0.1));
+        assert_eq!(size, target);
+        assert_eq!(position, Position::new(0., 0.));
+    }
+
+    #[test]
+    fn parse_size() {
+        assert_eq!("360x780".parse(), Ok(Size::new(360, 780)));
+        assert!("360+780".parse::<Size>().is_err());
+        assert!("360x".parse::<Size>().is_err());
+    }
+}
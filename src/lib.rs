@@ -0,0 +1,237 @@
+//! Minimal Wayland wallpaper tool.
+//!
+//! Besides the standalone `tabula` binary, this crate allows clients built on
+//! smithay-client-toolkit to embed a wallpaper surface into their own event
+//! loop using [`Wallpaper`].
+//!
+//! ```no_run
+//! # use smithay_client_toolkit::reexports::client::{Connection, QueueHandle};
+//! # use smithay_client_toolkit::reexports::client::globals::GlobalList;
+//! # use tabula::Wallpaper;
+//! # use tabula::wayland::WallpaperState;
+//! # fn example<D: WallpaperState>(
+//! #     connection: &Connection,
+//! #     globals: &GlobalList,
+//! #     queue: &QueueHandle<D>,
+//! # ) -> Result<(), tabula::Error> {
+//! let wallpaper: Wallpaper<D> = Wallpaper::builder()
+//!     .image("/usr/share/wallpapers/default.png")
+//!     .build(connection, globals, queue)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io;
+use std::time::Instant;
+
+use image::ImageError;
+use rustix::event::{self, PollFd, PollFlags, Timespec};
+use rustix::io::Errno;
+use smithay_client_toolkit::output::OutputState;
+use smithay_client_toolkit::reexports::client::backend::WaylandError;
+use smithay_client_toolkit::reexports::client::globals::{
+    self, BindError, GlobalError, GlobalList,
+};
+use smithay_client_toolkit::reexports::client::{
+    ConnectError, Connection, DispatchError, EventQueue, QueueHandle,
+};
+use smithay_client_toolkit::registry::RegistryState;
+use tracing::warn;
+
+use crate::cli::{Options, Subcommands};
+pub use crate::wallpaper::{Wallpaper, WallpaperBuilder};
+use crate::watcher::FileWatcher;
+
+mod backdrop;
+mod cache;
+pub mod cli;
+pub mod geometry;
+mod palette;
+mod pattern;
+mod power;
+mod renderer;
+mod shm;
+mod software;
+mod state;
+mod surface;
+#[cfg(test)]
+mod tests;
+mod text;
+mod transition;
+mod wallpaper;
+mod watcher;
+pub mod wayland;
+mod window;
+
+mod gl {
+    #![allow(clippy::all, unsafe_op_in_unsafe_fn)]
+    include!(concat!(env!("OUT_DIR"), "/gl_bindings.rs"));
+}
+
+#[doc(hidden)]
+pub mod reexports {
+    pub use smithay_client_toolkit::globals::GlobalData;
+    pub use smithay_client_toolkit::reexports::{client, protocols};
+}
+
+/// Run the standalone wallpaper application.
+pub fn run(options: Options) -> Result<(), Error> {
+    // Run subcommands without a Wayland connection.
+    match &options.subcommand {
+        Some(Subcommands::Render(render_options)) => {
+            return software::render_to_file(&options, render_options);
+        },
+        Some(Subcommands::Palette(palette_options)) => return palette::print(palette_options),
+        None => (),
+    }
+
+    // Watch the image files for changes.
+    let mut watcher: Option<FileWatcher> = None;
+    for path in options.image_paths() {
+        let result = match &mut watcher {
+            Some(watcher) => watcher.watch(path),
+            None => FileWatcher::new(path).map(|new_watcher| watcher = Some(new_watcher)),
+        };
+        if let Err(err) = result {
+            warn!("Image reload disabled, could not watch {path:?}: {err}");
+        }
+    }
+
+    // Initialize Wayland connection.
+    let connection = Connection::connect_to_env()?;
+    let (globals, mut queue) = globals::registry_queue_init(&connection)?;
+    // Persist the displayed state, to allow restoring it with `--restore`.
+    let mut state = State::new(&connection, &globals, &queue.handle(), options, true)?;
+
+    // Start event loop.
+    event_loop(&mut queue, &mut state, watcher)
+}
+
+/// Dispatch Wayland events and image changes until the wallpaper is closed.
+fn event_loop(
+    queue: &mut EventQueue<State>,
+    state: &mut State,
+    mut watcher: Option<FileWatcher>,
+) -> Result<(), Error> {
+    while !state.terminated {
+        queue.dispatch_pending(state)?;
+        state.reopen_closed();
+        queue.flush().map_err(DispatchError::Backend)?;
+
+        let guard = match queue.prepare_read() {
+            Some(guard) => guard,
+            None => continue,
+        };
+
+        // Wait for Wayland events, file changes, or the next timer.
+        let mut fds = vec![PollFd::from_borrowed_fd(guard.connection_fd(), PollFlags::IN)];
+        if let Some(watcher) = &watcher {
+            fds.push(PollFd::new(watcher, PollFlags::IN));
+        }
+        let update_timeout = state
+            .wallpaper
+            .next_update()
+            .map(|update| update.saturating_duration_since(Instant::now()));
+        let timeout = watcher.as_ref().and_then(FileWatcher::timeout).into_iter();
+        let timeout = timeout.chain(update_timeout).min();
+        let timeout = timeout.map(|timeout| Timespec::try_from(timeout).unwrap());
+        match event::poll(&mut fds, timeout.as_ref()) {
+            Ok(_) | Err(Errno::INTR) => (),
+            Err(err) => return Err(io::Error::from(err).into()),
+        }
+        let wayland_ready = !fds[0].revents().is_empty();
+        let watcher_ready = fds.get(1).is_some_and(|fd| !fd.revents().is_empty());
+        drop(fds);
+
+        // Read new Wayland events.
+        if wayland_ready {
+            match guard.read() {
+                Ok(_) => (),
+                Err(WaylandError::Io(err)) if err.kind() == io::ErrorKind::WouldBlock => (),
+                Err(err) => return Err(DispatchError::Backend(err).into()),
+            }
+        }
+
+        // Update clocks once their minute has passed, and apply scaled images.
+        if state.wallpaper.next_update().is_some_and(|update| update <= Instant::now()) {
+            state.wallpaper.update();
+        }
+
+        // Reload the image once it stopped changing.
+        if let Some(watcher) = &mut watcher {
+            if watcher_ready {
+                watcher.read_events()?;
+            }
+            if watcher.take_change() {
+                state.wallpaper.reload_image();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Application state.
+struct State {
+    wallpaper: Wallpaper<Self>,
+    registry: RegistryState,
+    output: OutputState,
+
+    /// Recreate the surface after it was closed, instead of exiting.
+    reopen: bool,
+    terminated: bool,
+}
+
+impl State {
+    fn new(
+        connection: &Connection,
+        globals: &GlobalList,
+        queue: &QueueHandle<Self>,
+        options: Options,
+        save_state: bool,
+    ) -> Result<Self, Error> {
+        let registry = RegistryState::new(globals);
+        let output = OutputState::new(globals, queue);
+
+        // Create the wallpaper's Wayland window.
+        let reopen = options.reopen;
+        let builder = Wallpaper::builder().options(options).save_state(save_state);
+        let wallpaper = builder.build(connection, globals, queue)?;
+
+        Ok(Self { wallpaper, registry, output, reopen, terminated: Default::default() })
+    }
+
+    /// Recreate the closed wallpaper, if any output can show it.
+    ///
+    /// This must be called after dispatching all pending events, since the
+    /// closed surface's output is usually removed right after closing it.
+    fn reopen_closed(&mut self) {
+        if self.wallpaper.is_closed() && self.output.outputs().next().is_some() {
+            self.wallpaper.reopen();
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Wayland protocol error for {0}: {1}")]
+    WaylandProtocol(&'static str, #[source] BindError),
+    #[error("{0}")]
+    WaylandDispatch(#[from] DispatchError),
+    #[error("{0}")]
+    WaylandConnect(#[from] ConnectError),
+    #[error("{0}")]
+    WaylandGlobal(#[from] GlobalError),
+    #[error("{0}")]
+    Glutin(#[from] glutin::error::Error),
+    #[error("{0}")]
+    Image(#[from] ImageError),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid font: {0}")]
+    Font(#[from] ab_glyph::InvalidFont),
+    #[error("no default font found, specify one with `font=PATH`")]
+    MissingFont,
+    #[error("invalid options: {0}")]
+    InvalidOptions(String),
+}
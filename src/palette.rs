@@ -0,0 +1,282 @@
+//! Color palette extraction.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use tracing::warn;
+
+use crate::Error;
+use crate::cli::{Color, Options, PaletteFormat, PaletteOptions, Rgb};
+use crate::window::UnloadedImage;
+
+/// Maximum dimensions of the image used for color quantization.
+const SAMPLE_SIZE: u32 = 64;
+
+/// Number of colors exported with `--palette-dir`.
+const DEFAULT_COLORS: usize = 8;
+
+/// Print the palette of an image.
+pub fn print(options: &PaletteOptions) -> Result<(), Error> {
+    let image = UnloadedImage::new(&options.path)?;
+    let palette = Palette::extract(&image, options.colors as usize);
+    print!("{}", palette.format(options.format));
+    Ok(())
+}
+
+/// Update the palette files of the wallpaper's image, if enabled.
+pub fn update(options: &Options, image: &UnloadedImage) {
+    if let Some(directory) = &options.palette_dir {
+        let palette = Palette::extract(image, DEFAULT_COLORS);
+        if let Err(err) = palette.export(directory) {
+            warn!("Failed to export palette to {directory:?}: {err}");
+        }
+    }
+}
+
+/// Resolve the background color, deriving automatic colors from the image.
+///
+/// Automatic colors fall back to black without an image.
+pub fn background(color: Color, image: Option<&UnloadedImage>) -> Rgb {
+    let (image, edge) = match (color, image) {
+        (Color::Rgb(rgb), _) => return rgb,
+        (Color::Auto, Some(image)) => (image, false),
+        (Color::AutoEdge, Some(image)) => (image, true),
+        (_, None) => return Rgb::default(),
+    };
+
+    // Downscale the image, since only its rough color distribution matters.
+    let sample = image.thumbnail(SAMPLE_SIZE);
+    let (width, height) = sample.dimensions();
+    let pixels: Vec<_> = sample
+        .enumerate_pixels()
+        .filter(|(x, y, _)| !edge || *x == 0 || *y == 0 || *x + 1 == width || *y + 1 == height)
+        .filter(|(.., pixel)| pixel[3] >= 128)
+        .map(|(.., pixel)| [pixel[0], pixel[1], pixel[2]])
+        .collect();
+
+    average(&pixels)
+}
+
+/// Image color palette.
+#[derive(PartialEq, Debug)]
+pub struct Palette {
+    /// Colors, ordered by the share of pixels they represent.
+    colors: Vec<Rgb>,
+    /// Most colorful entry of the palette.
+    accent: Rgb,
+}
+
+impl Palette {
+    /// Extract up to `count` colors from an image using median cut.
+    ///
+    /// Mostly transparent pixels are ignored.
+    pub fn extract(image: &UnloadedImage, count: usize) -> Self {
+        let pixels: Vec<_> = image
+            .thumbnail(SAMPLE_SIZE)
+            .pixels()
+            .filter(|pixel| pixel[3] >= 128)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect();
+
+        let mut colors = median_cut(pixels, count.max(1));
+        if colors.is_empty() {
+            colors.push(Rgb { r: 0, g: 0, b: 0 });
+        }
+
+        // Prefer a distinct accent, unless the image has only one color.
+        let candidates = if colors.len() > 1 { &colors[1..] } else { &colors[..] };
+        let accent = candidates.iter().copied().rev().max_by_key(|color| chroma(*color)).unwrap();
+
+        Self { colors, accent }
+    }
+
+    /// Most common color.
+    pub fn dominant(&self) -> Rgb {
+        self.colors[0]
+    }
+
+    /// Format the palette for consumption by other programs.
+    pub fn format(&self, format: PaletteFormat) -> String {
+        let dominant = self.dominant();
+        let accent = self.accent;
+
+        let mut output = String::new();
+        match format {
+            PaletteFormat::Json => {
+                let colors: Vec<_> =
+                    self.colors.iter().map(|color| format!("\"{color}\"")).collect();
+                let _ = writeln!(output, "{{");
+                let _ = writeln!(output, "  \"dominant\": \"{dominant}\",");
+                let _ = writeln!(output, "  \"accent\": \"{accent}\",");
+                let _ = writeln!(output, "  \"colors\": [{}]", colors.join(", "));
+                let _ = writeln!(output, "}}");
+            },
+            PaletteFormat::Shell => {
+                let _ = writeln!(output, "dominant='{dominant}'");
+                let _ = writeln!(output, "accent='{accent}'");
+                for (i, color) in self.colors.iter().enumerate() {
+                    let _ = writeln!(output, "color{i}='{color}'");
+                }
+            },
+            PaletteFormat::Css => {
+                let _ = writeln!(output, ":root {{");
+                let _ = writeln!(output, "  --dominant: {dominant};");
+                let _ = writeln!(output, "  --accent: {accent};");
+                for (i, color) in self.colors.iter().enumerate() {
+                    let _ = writeln!(output, "  --color{i}: {color};");
+                }
+                let _ = writeln!(output, "}}");
+            },
+        }
+        output
+    }
+
+    /// Write the palette to a directory in all formats.
+    ///
+    /// This creates the `palette.json`, `palette.sh` and `palette.css` files.
+    pub fn export(&self, directory: &Path) -> Result<(), Error> {
+        fs::create_dir_all(directory)?;
+
+        let formats = [
+            (PaletteFormat::Json, "json"),
+            (PaletteFormat::Shell, "sh"),
+            (PaletteFormat::Css, "css"),
+        ];
+        for (format, extension) in formats {
+            // Replace files atomically, to avoid partial reads by other programs.
+            let path = directory.join(format!("palette.{extension}"));
+            let tmp_path = directory.join(format!(".palette.{extension}.tmp"));
+            fs::write(&tmp_path, self.format(format))?;
+            fs::rename(&tmp_path, &path)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Quantize pixels into up to `count` colors, ordered by their population.
+fn median_cut(pixels: Vec<[u8; 3]>, count: usize) -> Vec<Rgb> {
+    let mut boxes = vec![pixels];
+    boxes.retain(|pixels| !pixels.is_empty());
+
+    while boxes.len() < count {
+        // Split the box with the widest channel range at its median.
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, pixels)| pixels.len() > 1)
+            .map(|(i, pixels)| {
+                let (channel, range) = widest_channel(pixels);
+                (i, channel, range)
+            })
+            .max_by_key(|(.., range)| *range);
+
+        let (index, channel) = match widest {
+            Some((index, channel, range)) if range > 0 => (index, channel),
+            _ => break,
+        };
+
+        let mut pixels = boxes.swap_remove(index);
+        pixels.sort_unstable_by_key(|pixel| pixel[channel]);
+
+        // Split next to the median, without separating pixels of equal value.
+        let median = pixels[pixels.len() / 2][channel];
+        let before = pixels.partition_point(|pixel| pixel[channel] < median);
+        let after = pixels.partition_point(|pixel| pixel[channel] <= median);
+        let split = match (before, after) {
+            (0, after) => after,
+            (before, after) if after == pixels.len() => before,
+            (before, after) if pixels.len() / 2 - before <= after - pixels.len() / 2 => before,
+            (_, after) => after,
+        };
+
+        let upper = pixels.split_off(split);
+        boxes.push(pixels);
+        boxes.push(upper);
+    }
+
+    boxes.sort_by_key(|pixels| std::cmp::Reverse(pixels.len()));
+    boxes.iter().map(|pixels| average(pixels)).collect()
+}
+
+/// Find the color channel with the largest range of values.
+fn widest_channel(pixels: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let min = pixels.iter().map(|pixel| pixel[channel]).min().unwrap_or(0);
+            let max = pixels.iter().map(|pixel| pixel[channel]).max().unwrap_or(0);
+            (channel, max - min)
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap()
+}
+
+/// Average color of a group of pixels.
+fn average(pixels: &[[u8; 3]]) -> Rgb {
+    let mut sum = [0u64; 3];
+    for pixel in pixels {
+        for channel in 0..3 {
+            sum[channel] += pixel[channel] as u64;
+        }
+    }
+
+    let len = pixels.len().max(1) as u64;
+    let [r, g, b] = sum.map(|channel| ((channel + len / 2) / len) as u8);
+    Rgb { r, g, b }
+}
+
+/// Colorfulness of a color.
+fn chroma(color: Rgb) -> u8 {
+    let max = color.r.max(color.g).max(color.b);
+    let min = color.r.min(color.g).min(color.b);
+    max - min
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+
+    #[test]
+    fn dominant_and_accent() {
+        // Mostly gray, with a blue stripe and a transparent red stripe.
+        let image = RgbaImage::from_fn(10, 10, |x, _| match x {
+            0..7 => Rgba([128, 128, 128, 255]),
+            7..9 => Rgba([0, 0, 255, 255]),
+            _ => Rgba([255, 0, 0, 0]),
+        });
+        let palette = Palette::extract(&image.into(), 4);
+
+        let gray = Rgb { r: 128, g: 128, b: 128 };
+        let blue = Rgb { r: 0, g: 0, b: 255 };
+        assert_eq!(palette, Palette { colors: vec![gray, blue], accent: blue });
+
+        assert_eq!(
+            palette.format(PaletteFormat::Shell),
+            "dominant='#808080'\naccent='#0000ff'\ncolor0='#808080'\ncolor1='#0000ff'\n"
+        );
+        assert!(palette.format(PaletteFormat::Css).contains("  --accent: #0000ff;\n"));
+        assert!(
+            palette.format(PaletteFormat::Json).contains("\"colors\": [\"#808080\", \"#0000ff\"]")
+        );
+    }
+
+    #[test]
+    fn auto_background() {
+        // White center, surrounded by a black border.
+        let image: UnloadedImage = RgbaImage::from_fn(4, 4, |x, y| match (x, y) {
+            (1..3, 1..3) => Rgba([255, 255, 255, 255]),
+            _ => Rgba([0, 0, 0, 255]),
+        })
+        .into();
+
+        assert_eq!(background(Color::Auto, Some(&image)), Rgb { r: 64, g: 64, b: 64 });
+        assert_eq!(background(Color::AutoEdge, Some(&image)), Rgb { r: 0, g: 0, b: 0 });
+        assert_eq!(background(Color::Auto, None), Rgb { r: 0, g: 0, b: 0 });
+
+        let red = Rgb { r: 255, g: 0, b: 0 };
+        assert_eq!(background(Color::Rgb(red), Some(&image)), red);
+    }
+}
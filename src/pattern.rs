@@ -0,0 +1,135 @@
+//! Procedural background patterns.
+//!
+//! This mirrors `shaders/pattern_fragment.glsl`, to render patterns without
+//! OpenGL.
+
+use chrono::{DateTime, Local, Timelike};
+
+use crate::cli::{Pattern, PatternKind, Rgb};
+
+/// Sky gradient keyframes, as hour with top and bottom colors.
+const SKY: [(f32, [u32; 2]); 7] = [
+    (0., [0x0B1026, 0x1C2541]),
+    (5., [0x0B1026, 0x1C2541]),
+    (7., [0x3A6EA5, 0xF5A65B]),
+    (9., [0x3F8FD2, 0xA9D6F5]),
+    (17., [0x3F8FD2, 0xA9D6F5]),
+    (19.5, [0x2E3F7F, 0xF07F4F]),
+    (21.5, [0x0B1026, 0x1C2541]),
+];
+
+/// Get the pattern's two colors.
+///
+/// For the sky pattern, these are the top and bottom colors at `now`.
+pub fn colors(pattern: &Pattern, background: Rgb, now: &DateTime<Local>) -> [[f32; 3]; 2] {
+    match pattern.kind {
+        PatternKind::Sky => sky_colors(now),
+        _ => [to_float(background), to_float(pattern.color)],
+    }
+}
+
+/// Get the pattern's color at a physical pixel.
+///
+/// The `cell_size` is the pattern's scale in physical pixels, while `x` and
+/// `y` are the pixel's center with a top-left origin.
+pub fn sample(
+    kind: PatternKind,
+    colors: [[f32; 3]; 2],
+    cell_size: f32,
+    height: f32,
+    x: f32,
+    y: f32,
+) -> [f32; 3] {
+    let (cell_x, cell_y) = (x / cell_size, y / cell_size);
+
+    let t = match kind {
+        PatternKind::Checkerboard => (cell_x.floor() + cell_y.floor()).rem_euclid(2.),
+        PatternKind::Stripes => {
+            if (cell_x + cell_y).rem_euclid(1.) < 0.5 {
+                0.
+            } else {
+                1.
+            }
+        },
+        PatternKind::Dots => {
+            let dx = cell_x - cell_x.floor() - 0.5;
+            let dy = cell_y - cell_y.floor() - 0.5;
+            let distance = (dx * dx + dy * dy).sqrt() * cell_size;
+            (0.25 * cell_size - distance + 0.5).clamp(0., 1.)
+        },
+        PatternKind::Noise => {
+            let (i_x, i_y) = (cell_x.floor(), cell_y.floor());
+            let (f_x, f_y) = (cell_x - i_x, cell_y - i_y);
+            let (u_x, u_y) = (f_x * f_x * (3. - 2. * f_x), f_y * f_y * (3. - 2. * f_y));
+            let top = mix(hash(i_x, i_y), hash(i_x + 1., i_y), u_x);
+            let bottom = mix(hash(i_x, i_y + 1.), hash(i_x + 1., i_y + 1.), u_x);
+            mix(top, bottom, u_y)
+        },
+        PatternKind::Sky => y / height,
+    };
+
+    std::array::from_fn(|i| mix(colors[0][i], colors[1][i], t))
+}
+
+/// Pseudo-random value for a noise lattice point.
+fn hash(x: f32, y: f32) -> f32 {
+    let value = (x * 12.9898 + y * 78.233).sin() * 43_758.547;
+    value - value.floor()
+}
+
+/// Get the sky's top and bottom colors for a time of day.
+fn sky_colors(now: &DateTime<Local>) -> [[f32; 3]; 2] {
+    let hour = now.hour() as f32 + now.minute() as f32 / 60.;
+
+    // Find the keyframes surrounding the current time, wrapping around midnight.
+    let next_index = SKY.iter().position(|(start, _)| *start > hour).unwrap_or(0);
+    let (start, start_colors) = SKY[(next_index + SKY.len() - 1) % SKY.len()];
+    let (mut end, end_colors) = SKY[next_index];
+    if end <= start {
+        end += 24.;
+    }
+    let t = (hour - start) / (end - start);
+
+    std::array::from_fn(|i| {
+        let start = to_float(rgb(start_colors[i]));
+        let end = to_float(rgb(end_colors[i]));
+        std::array::from_fn(|channel| mix(start[channel], end[channel], t))
+    })
+}
+
+/// Linearly interpolate between two values.
+fn mix(start: f32, end: f32, t: f32) -> f32 {
+    start + (end - start) * t
+}
+
+/// Convert a hex color to RGB.
+fn rgb(color: u32) -> Rgb {
+    Rgb { r: (color >> 16) as u8, g: (color >> 8) as u8, b: color as u8 }
+}
+
+/// Convert a color to normalized floats.
+fn to_float(color: Rgb) -> [f32; 3] {
+    [color.r as f32 / 255., color.g as f32 / 255., color.b as f32 / 255.]
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn sky_keyframes() {
+        let noon = Local.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap();
+        assert_eq!(sky_colors(&noon), [to_float(rgb(0x3F8FD2)), to_float(rgb(0xA9D6F5))]);
+
+        // Night wraps around midnight.
+        let night = Local.with_ymd_and_hms(2024, 6, 1, 23, 30, 0).unwrap();
+        assert_eq!(sky_colors(&night), [to_float(rgb(0x0B1026)), to_float(rgb(0x1C2541))]);
+
+        // Colors are interpolated between keyframes.
+        let dawn = Local.with_ymd_and_hms(2024, 6, 1, 6, 0, 0).unwrap();
+        let [top, _] = sky_colors(&dawn);
+        assert!(top[2] > 0x26 as f32 / 255. && top[2] < 0xA5 as f32 / 255.);
+    }
+}
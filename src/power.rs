@@ -0,0 +1,136 @@
+//! Battery-aware animation throttling.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Default sysfs directory listing all power supplies.
+const POWER_SUPPLY_PATH: &str = "/sys/class/power_supply";
+
+/// Minimum time between animation frames at a reduced rate.
+const REDUCED_FRAME_INTERVAL: Duration = Duration::from_millis(33);
+
+/// Animation rate allowed by the current power source.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Throttle {
+    /// Animations run at their full rate.
+    None,
+    /// Animations run at a reduced rate, to save power on battery.
+    Reduced,
+    /// Animations are frozen, since the battery is low.
+    Frozen,
+}
+
+impl Throttle {
+    /// Minimum time between frames of continuous animations.
+    pub fn frame_interval(self) -> Duration {
+        match self {
+            Self::Reduced => REDUCED_FRAME_INTERVAL,
+            Self::None | Self::Frozen => Duration::ZERO,
+        }
+    }
+}
+
+/// Power supply monitor.
+pub struct PowerSupply {
+    path: PathBuf,
+    threshold: u8,
+}
+
+impl PowerSupply {
+    /// Create a monitor freezing animations below `threshold` percent charge.
+    pub fn new(threshold: u8) -> Self {
+        Self::with_path(POWER_SUPPLY_PATH, threshold)
+    }
+
+    fn with_path(path: impl Into<PathBuf>, threshold: u8) -> Self {
+        Self { path: path.into(), threshold }
+    }
+
+    /// Get the animation throttling for the current power source.
+    ///
+    /// Systems without any battery, like most desktops, are never throttled.
+    pub fn throttle(&self) -> Throttle {
+        let entries = match fs::read_dir(&self.path) {
+            Ok(entries) => entries,
+            Err(_) => return Throttle::None,
+        };
+
+        let mut capacities = Vec::new();
+        let mut discharging = false;
+        for entry in entries.flatten() {
+            let supply = entry.path();
+            let attribute = |name| read_attribute(&supply, name);
+            match attribute("type").as_deref() {
+                // Any connected charger lifts all restrictions.
+                Some("Mains" | "USB") if attribute("online").as_deref() == Some("1") => {
+                    return Throttle::None;
+                },
+                // Ignore batteries of peripherals like mice and headphones.
+                Some("Battery") if attribute("scope").as_deref() != Some("Device") => {
+                    discharging |= attribute("status").as_deref() == Some("Discharging");
+                    capacities
+                        .extend(attribute("capacity").and_then(|value| value.parse::<u32>().ok()));
+                },
+                _ => (),
+            }
+        }
+
+        if !discharging {
+            return Throttle::None;
+        }
+
+        // Average the charge of all system batteries.
+        let capacity = match capacities.len() {
+            0 => return Throttle::Reduced,
+            count => capacities.iter().sum::<u32>() / count as u32,
+        };
+
+        if capacity < self.threshold as u32 { Throttle::Frozen } else { Throttle::Reduced }
+    }
+}
+
+/// Read a power supply's sysfs attribute.
+fn read_attribute(supply: &Path, attribute: &str) -> Option<String> {
+    let value = fs::read_to_string(supply.join(attribute)).ok()?;
+    Some(value.trim().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn battery_throttle() {
+        let path = std::env::temp_dir().join(format!("tabula-power-{}", std::process::id()));
+        let supply = |name: &str, attributes: &[(&str, &str)]| {
+            let directory = path.join(name);
+            fs::create_dir_all(&directory).unwrap();
+            for (attribute, value) in attributes {
+                fs::write(directory.join(attribute), format!("{value}\n")).unwrap();
+            }
+        };
+        let power = PowerSupply::with_path(&path, 20);
+
+        // Desktops without batteries are never throttled.
+        supply("AC", &[("type", "Mains"), ("online", "0")]);
+        assert_eq!(power.throttle(), Throttle::None);
+
+        supply("BAT0", &[("type", "Battery"), ("status", "Discharging"), ("capacity", "50")]);
+        assert_eq!(power.throttle(), Throttle::Reduced);
+
+        // Peripheral batteries are ignored.
+        let mouse = [("type", "Battery"), ("scope", "Device"), ("capacity", "5")];
+        supply("hid-mouse", &mouse);
+        assert_eq!(power.throttle(), Throttle::Reduced);
+
+        supply("BAT0", &[("capacity", "19")]);
+        assert_eq!(power.throttle(), Throttle::Frozen);
+
+        // Connecting a charger resumes animations.
+        supply("AC", &[("online", "1")]);
+        assert_eq!(power.throttle(), Throttle::None);
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -5,22 +5,30 @@ use std::num::NonZeroU32;
 use std::ptr::NonNull;
 use std::{mem, ptr};
 
-use glutin::config::{Api, ConfigTemplateBuilder};
+use glutin::config::{Api, Config, ConfigTemplateBuilder};
 use glutin::context::{ContextApi, ContextAttributesBuilder, PossiblyCurrentContext, Version};
 use glutin::display::Display;
 use glutin::prelude::*;
-use glutin::surface::{Surface, SurfaceAttributesBuilder, SwapInterval, WindowSurface};
+use glutin::surface::{Rect, Surface, SurfaceAttributesBuilder, SwapInterval, WindowSurface};
 use raw_window_handle::{RawWindowHandle, WaylandWindowHandle};
 use smithay_client_toolkit::reexports::client::Proxy;
+use smithay_client_toolkit::reexports::client::protocol::wl_output::Transform;
 use smithay_client_toolkit::reexports::client::protocol::wl_surface::WlSurface;
+use tracing::{debug, warn};
 
-use crate::geometry::{Position, Size};
+use crate::cli::PatternKind;
+use crate::geometry::{self, Position, Size};
 use crate::gl;
-use crate::gl::types::{GLfloat, GLint, GLuint};
+use crate::gl::types::{GLenum, GLfloat, GLint, GLuint};
 
 // OpenGL shader programs.
 const VERTEX_SHADER: &str = include_str!("../shaders/vertex.glsl");
 const FRAGMENT_SHADER: &str = include_str!("../shaders/fragment.glsl");
+const PATTERN_VERTEX_SHADER: &str = include_str!("../shaders/pattern_vertex.glsl");
+const PATTERN_FRAGMENT_SHADER: &str = include_str!("../shaders/pattern_fragment.glsl");
+
+/// Attribute location of the vertex positions.
+const VERTEX_POSITION_LOCATION: GLuint = 0;
 
 /// OpenGL renderer.
 #[derive(Debug)]
@@ -28,6 +36,11 @@ pub struct Renderer {
     sized: Option<SizedRenderer>,
     surface: WlSurface,
     display: Display,
+
+    /// Surface size before the buffer transform.
+    size: Size,
+    /// Clip space matrix applying the buffer transform.
+    transform: [GLfloat; 4],
 }
 
 impl Renderer {
@@ -39,12 +52,64 @@ impl Renderer {
             display.get_proc_address(symbol.as_c_str()).cast()
         });
 
-        Renderer { surface, display, sized: Default::default() }
+        Renderer {
+            surface,
+            display,
+            transform: transform_matrix(Transform::Normal),
+            sized: Default::default(),
+            size: Default::default(),
+        }
+    }
+
+    /// Replace the surface drawn to.
+    ///
+    /// The EGL context is discarded and will be recreated on the next draw, so
+    /// all textures must be recreated.
+    pub fn set_surface(&mut self, surface: WlSurface) {
+        self.sized = None;
+        self.surface = surface;
     }
 
     /// Perform drawing with this renderer mapped.
-    pub fn draw<F: FnOnce(&Renderer)>(&mut self, size: Size, fun: F) {
-        self.sized(size).make_current();
+    ///
+    /// Drawing happens in the surface's coordinate space, while the buffer is
+    /// pre-rotated according to its `transform`.
+    ///
+    /// Only the `damage` rectangles in physical surface coordinates are
+    /// reported as changed, if EGL supports it. Without any rectangles, the
+    /// entire buffer is damaged.
+    ///
+    /// If drawing fails, the EGL context is discarded and will be recreated on
+    /// the next draw. All textures must be recreated in that case.
+    pub fn draw<F: FnOnce(&Renderer)>(
+        &mut self,
+        size: Size,
+        transform: Transform,
+        damage: &[(Position, Size)],
+        fun: F,
+    ) -> Result<(), RendererError> {
+        self.size = size;
+        self.transform = transform_matrix(transform);
+
+        let damage = buffer_damage(damage, size, transform);
+        let result = self.try_draw(geometry::transform_size(transform, size), &damage, fun);
+
+        // Drop EGL state after failures, to recover from context loss.
+        if result.is_err() {
+            self.sized = None;
+        }
+
+        result
+    }
+
+    /// Attempt to draw into a buffer with this renderer mapped.
+    fn try_draw<F: FnOnce(&Renderer)>(
+        &mut self,
+        size: Size,
+        damage: &[Rect],
+        fun: F,
+    ) -> Result<(), RendererError> {
+        self.sized(size)?.make_current()?;
 
         // Resize OpenGL viewport.
         //
@@ -55,7 +120,7 @@ impl Renderer {
 
         unsafe { gl::Flush() };
 
-        self.sized(size).swap_buffers();
+        self.sized(size)?.swap_buffers(damage)
     }
 
     /// Render texture at a position in viewport-coordinates.
@@ -63,6 +128,9 @@ impl Renderer {
     /// Specifying a `size` will automatically scale the texture to render at
     /// the desired size. Otherwise the texture's size will be used instead.
     ///
+    /// The texture is blended with the existing content based on its alpha
+    /// channel, multiplied by `opacity`.
+    ///
     /// # Safety
     ///
     /// This must be called from within [`Self::draw`], to ensure the correct
@@ -72,6 +140,7 @@ impl Renderer {
         texture: &Texture,
         mut position: Position<f32>,
         size: impl Into<Option<Size<f32>>>,
+        opacity: f32,
     ) {
         // Fail before renderer initialization.
         //
@@ -89,16 +158,21 @@ impl Renderer {
 
         unsafe {
             // Matrix transforming vertex positions to desired size.
-            let size: Size<f32> = sized.size.into();
+            let size: Size<f32> = self.size.into();
             let x_scale = width / size.width;
             let y_scale = height / size.height;
             let matrix = [x_scale, 0., 0., y_scale];
-            gl::UniformMatrix2fv(sized.uniform_matrix, 1, gl::FALSE, matrix.as_ptr());
+            let program = &sized.texture_program;
+            gl::UseProgram(program.id);
+            gl::UniformMatrix2fv(program.uniform_matrix, 1, gl::FALSE, matrix.as_ptr());
+            gl::UniformMatrix2fv(program.uniform_transform, 1, gl::FALSE, self.transform.as_ptr());
 
             // Set texture position offset.
             position.x /= size.width / 2.;
             position.y /= size.height / 2.;
-            gl::Uniform2fv(sized.uniform_position, 1, [position.x, -position.y].as_ptr());
+            gl::Uniform2fv(program.uniform_position, 1, [position.x, -position.y].as_ptr());
+
+            gl::Uniform1f(program.uniform_opacity, opacity);
 
             gl::BindTexture(gl::TEXTURE_2D, texture.id);
 
@@ -106,19 +180,46 @@ impl Renderer {
         }
     }
 
+    /// Fill the viewport with a procedural pattern.
+    ///
+    /// The `cell_size` is the pattern's scale in physical pixels.
+    ///
+    /// # Safety
+    ///
+    /// This must be called from within [`Self::draw`].
+    pub unsafe fn draw_pattern(&self, kind: PatternKind, colors: [[f32; 3]; 2], cell_size: f32) {
+        let sized = match &self.sized {
+            Some(sized) => sized,
+            None => unreachable!(),
+        };
+
+        unsafe {
+            let program = &sized.pattern_program;
+            gl::UseProgram(program.id);
+            gl::Uniform1i(program.uniform_kind, kind as GLint);
+            gl::Uniform3fv(program.uniform_colors, 2, colors.as_flattened().as_ptr());
+            gl::Uniform1f(program.uniform_cell_size, cell_size);
+            let size: Size<f32> = self.size.into();
+            gl::Uniform2f(program.uniform_resolution, size.width, size.height);
+            gl::UniformMatrix2fv(program.uniform_transform, 1, gl::FALSE, self.transform.as_ptr());
+
+            gl::DrawArrays(gl::TRIANGLES, 0, 6);
+        }
+    }
+
     /// Get render state requiring a size.
-    fn sized(&mut self, size: Size) -> &SizedRenderer {
+    fn sized(&mut self, size: Size) -> Result<&SizedRenderer, RendererError> {
         // Initialize or resize sized state.
         match &mut self.sized {
             // Resize renderer.
             Some(sized) => sized.resize(size),
             // Create sized state.
             None => {
-                self.sized = Some(SizedRenderer::new(&self.display, &self.surface, size));
+                self.sized = Some(SizedRenderer::new(&self.display, &self.surface, size)?);
             },
         }
 
-        self.sized.as_ref().unwrap()
+        Ok(self.sized.as_ref().unwrap())
     }
 }
 
@@ -128,8 +229,8 @@ impl Renderer {
 /// before the first draw.
 #[derive(Debug)]
 struct SizedRenderer {
-    uniform_position: GLint,
-    uniform_matrix: GLint,
+    texture_program: TextureProgram,
+    pattern_program: PatternProgram,
 
     egl_surface: Surface<WindowSurface>,
     egl_context: PossiblyCurrentContext,
@@ -139,14 +240,14 @@ struct SizedRenderer {
 
 impl SizedRenderer {
     /// Create sized renderer state.
-    fn new(display: &Display, surface: &WlSurface, size: Size) -> Self {
+    fn new(display: &Display, surface: &WlSurface, size: Size) -> Result<Self, RendererError> {
         // Create EGL surface and context and make it current.
-        let (egl_surface, egl_context) = Self::create_surface(display, surface, size);
+        let (egl_surface, egl_context) = Self::create_surface(display, surface, size)?;
 
         // Setup OpenGL program.
-        let (uniform_position, uniform_matrix) = Self::create_program();
+        let (texture_program, pattern_program) = Self::create_programs();
 
-        Self { uniform_position, uniform_matrix, egl_surface, egl_context, size }
+        Ok(Self { texture_program, pattern_program, egl_surface, egl_context, size })
     }
 
     /// Resize the renderer.
@@ -166,39 +267,62 @@ impl SizedRenderer {
     }
 
     /// Make EGL surface current.
-    fn make_current(&self) {
-        self.egl_context.make_current(&self.egl_surface).unwrap();
+    fn make_current(&self) -> Result<(), RendererError> {
+        self.egl_context.make_current(&self.egl_surface).map_err(RendererError::MakeCurrent)
     }
 
-    /// Perform OpenGL buffer swap.
-    fn swap_buffers(&self) {
-        self.egl_surface.swap_buffers(&self.egl_context).unwrap();
+    /// Perform OpenGL buffer swap, damaging only `damage`.
+    ///
+    /// This falls back to damaging the entire buffer, without support for
+    /// `EGL_KHR_swap_buffers_with_damage` or
+    /// `EGL_EXT_swap_buffers_with_damage`.
+    fn swap_buffers(&self, damage: &[Rect]) -> Result<(), RendererError> {
+        // Only the EGL backend is enabled, which exposes damage tracking.
+        let (Surface::Egl(surface), PossiblyCurrentContext::Egl(context)) =
+            (&self.egl_surface, &self.egl_context);
+        surface.swap_buffers_with_damage(context, damage).map_err(RendererError::SwapBuffers)
     }
 
     /// Create a new EGL surface.
+    ///
+    /// All matching EGL configs are tried in order, until one of them works.
     fn create_surface(
         display: &Display,
         surface: &WlSurface,
         size: Size,
-    ) -> (Surface<WindowSurface>, PossiblyCurrentContext) {
+    ) -> Result<(Surface<WindowSurface>, PossiblyCurrentContext), RendererError> {
         assert!(size.width > 0 && size.height > 0);
 
-        // Create EGL config.
+        // Find all EGL configs supporting GLES2.
         let config_template = ConfigTemplateBuilder::new().with_api(Api::GLES2).build();
-        let egl_config = unsafe {
-            display
-                .find_configs(config_template)
-                .ok()
-                .and_then(|mut configs| configs.next())
-                .unwrap()
-        };
+        let egl_configs =
+            unsafe { display.find_configs(config_template) }.map_err(RendererError::Context)?;
+
+        let mut result = Err(RendererError::NoConfig);
+        for egl_config in egl_configs {
+            result = Self::create_surface_with_config(display, &egl_config, surface, size);
+            match &result {
+                Ok(_) => break,
+                Err(err) => debug!("Skipping EGL config: {err}"),
+            }
+        }
 
+        result
+    }
+
+    /// Create a new EGL surface using a specific config.
+    fn create_surface_with_config(
+        display: &Display,
+        egl_config: &Config,
+        surface: &WlSurface,
+        size: Size,
+    ) -> Result<(Surface<WindowSurface>, PossiblyCurrentContext), RendererError> {
         // Create EGL context.
         let context_attributes = ContextAttributesBuilder::new()
             .with_context_api(ContextApi::Gles(Some(Version::new(2, 0))))
             .build(None);
-        let egl_context =
-            unsafe { display.create_context(&egl_config, &context_attributes).unwrap() };
+        let egl_context = unsafe { display.create_context(egl_config, &context_attributes) }
+            .map_err(RendererError::Context)?;
         let egl_context = egl_context.treat_as_possibly_current();
 
         let surface = NonNull::new(surface.id().as_ptr().cast()).unwrap();
@@ -210,45 +334,23 @@ impl SizedRenderer {
             NonZeroU32::new(size.height).unwrap(),
         );
 
-        let egl_surface =
-            unsafe { display.create_window_surface(&egl_config, &surface_attributes).unwrap() };
+        let egl_surface = unsafe { display.create_window_surface(egl_config, &surface_attributes) }
+            .map_err(RendererError::Surface)?;
 
         // Ensure rendering never blocks.
-        egl_context.make_current(&egl_surface).unwrap();
-        egl_surface.set_swap_interval(&egl_context, SwapInterval::DontWait).unwrap();
+        egl_context.make_current(&egl_surface).map_err(RendererError::MakeCurrent)?;
+        if let Err(err) = egl_surface.set_swap_interval(&egl_context, SwapInterval::DontWait) {
+            warn!("Could not disable vsync: {err}");
+        }
 
-        (egl_surface, egl_context)
+        Ok((egl_surface, egl_context))
     }
 
-    /// Create the OpenGL program.
-    fn create_program() -> (GLint, GLint) {
+    /// Create the OpenGL programs.
+    fn create_programs() -> (TextureProgram, PatternProgram) {
         unsafe {
-            // Create vertex shader.
-            let vertex_shader = gl::CreateShader(gl::VERTEX_SHADER);
-            gl::ShaderSource(
-                vertex_shader,
-                1,
-                [VERTEX_SHADER.as_ptr()].as_ptr() as *const _,
-                &(VERTEX_SHADER.len() as i32) as *const _,
-            );
-            gl::CompileShader(vertex_shader);
-
-            // Create fragment shader.
-            let fragment_shader = gl::CreateShader(gl::FRAGMENT_SHADER);
-            gl::ShaderSource(
-                fragment_shader,
-                1,
-                [FRAGMENT_SHADER.as_ptr()].as_ptr() as *const _,
-                &(FRAGMENT_SHADER.len() as i32) as *const _,
-            );
-            gl::CompileShader(fragment_shader);
-
-            // Create shader program.
-            let program = gl::CreateProgram();
-            gl::AttachShader(program, vertex_shader);
-            gl::AttachShader(program, fragment_shader);
-            gl::LinkProgram(program);
-            gl::UseProgram(program);
+            let texture_program = compile_program(VERTEX_SHADER, FRAGMENT_SHADER);
+            let pattern_program = compile_program(PATTERN_VERTEX_SHADER, PATTERN_FRAGMENT_SHADER);
 
             // Generate VBO.
             let mut vbo = 0;
@@ -273,27 +375,159 @@ impl SizedRenderer {
                 gl::STATIC_DRAW,
             );
 
-            // Define VBO layout.
-            let location = gl::GetAttribLocation(program, c"aVertexPosition".as_ptr()) as GLuint;
+            // Define VBO layout, shared by all programs.
             gl::VertexAttribPointer(
-                location,
+                VERTEX_POSITION_LOCATION,
                 2,
                 gl::FLOAT,
                 gl::FALSE,
                 2 * mem::size_of::<GLfloat>() as i32,
                 ptr::null(),
             );
-            gl::EnableVertexAttribArray(0);
+            gl::EnableVertexAttribArray(VERTEX_POSITION_LOCATION);
 
             // Get uniform locations.
-            let uniform_position = gl::GetUniformLocation(program, c"uPosition".as_ptr());
-            let uniform_matrix = gl::GetUniformLocation(program, c"uMatrix".as_ptr());
-
-            (uniform_position, uniform_matrix)
+            let texture_program = TextureProgram {
+                uniform_position: gl::GetUniformLocation(texture_program, c"uPosition".as_ptr()),
+                uniform_matrix: gl::GetUniformLocation(texture_program, c"uMatrix".as_ptr()),
+                uniform_opacity: gl::GetUniformLocation(texture_program, c"uOpacity".as_ptr()),
+                uniform_transform: gl::GetUniformLocation(texture_program, c"uTransform".as_ptr()),
+                id: texture_program,
+            };
+            let pattern_program = PatternProgram {
+                uniform_kind: gl::GetUniformLocation(pattern_program, c"uKind".as_ptr()),
+                uniform_colors: gl::GetUniformLocation(pattern_program, c"uColors".as_ptr()),
+                uniform_cell_size: gl::GetUniformLocation(pattern_program, c"uCellSize".as_ptr()),
+                uniform_resolution: gl::GetUniformLocation(
+                    pattern_program,
+                    c"uResolution".as_ptr(),
+                ),
+                uniform_transform: gl::GetUniformLocation(pattern_program, c"uTransform".as_ptr()),
+                id: pattern_program,
+            };
+
+            // Blend textures with the background, without affecting its opacity.
+            gl::Enable(gl::BLEND);
+            gl::BlendFuncSeparate(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA, gl::ZERO, gl::ONE);
+
+            (texture_program, pattern_program)
         }
     }
 }
 
+/// Program for drawing textures.
+#[derive(Debug)]
+struct TextureProgram {
+    id: GLuint,
+    uniform_position: GLint,
+    uniform_matrix: GLint,
+    uniform_opacity: GLint,
+    uniform_transform: GLint,
+}
+
+/// Program for drawing procedural patterns.
+#[derive(Debug)]
+struct PatternProgram {
+    id: GLuint,
+    uniform_kind: GLint,
+    uniform_colors: GLint,
+    uniform_cell_size: GLint,
+    uniform_resolution: GLint,
+    uniform_transform: GLint,
+}
+
+/// Get the clip space matrix mapping surface to buffer coordinates.
+///
+/// The matrix is in column-major order.
+fn transform_matrix(transform: Transform) -> [GLfloat; 4] {
+    match transform {
+        Transform::Flipped => [-1., 0., 0., 1.],
+        Transform::_90 => [0., -1., 1., 0.],
+        Transform::Flipped90 => [0., 1., 1., 0.],
+        Transform::_180 => [-1., 0., 0., -1.],
+        Transform::Flipped180 => [1., 0., 0., -1.],
+        Transform::_270 => [0., 1., -1., 0.],
+        Transform::Flipped270 => [0., -1., -1., 0.],
+        _ => [1., 0., 0., 1.],
+    }
+}
+
+/// Convert damage in physical surface coordinates to EGL buffer rectangles.
+///
+/// This applies the buffer `transform` like [`transform_matrix`], with EGL's
+/// origin in the buffer's bottom-left corner.
+fn buffer_damage(damage: &[(Position, Size)], size: Size, transform: Transform) -> Vec<Rect> {
+    let (width, height) = (size.width as i32, size.height as i32);
+    let buffer_height = geometry::transform_size(transform, size).height as i32;
+    damage
+        .iter()
+        .map(|(position, rect_size)| {
+            let (x, y) = (position.x, position.y);
+            let (w, h) = (rect_size.width as i32, rect_size.height as i32);
+            let (x, y, w, h) = match transform {
+                Transform::_90 => (height - y - h, x, h, w),
+                Transform::_180 => (width - x - w, height - y - h, w, h),
+                Transform::_270 => (y, width - x - w, h, w),
+                Transform::Flipped => (width - x - w, y, w, h),
+                Transform::Flipped90 => (height - y - h, width - x - w, h, w),
+                Transform::Flipped180 => (x, height - y - h, w, h),
+                Transform::Flipped270 => (y, x, h, w),
+                _ => (x, y, w, h),
+            };
+            Rect::new(x, buffer_height - y - h, w, h)
+        })
+        .collect()
+}
+
+/// Compile and link a shader program.
+///
+/// The vertex position attribute is always bound to
+/// [`VERTEX_POSITION_LOCATION`], so all programs can share one VBO.
+unsafe fn compile_program(vertex_source: &str, fragment_source: &str) -> GLuint {
+    unsafe {
+        let vertex_shader = compile_shader(gl::VERTEX_SHADER, vertex_source);
+        let fragment_shader = compile_shader(gl::FRAGMENT_SHADER, fragment_source);
+
+        let program = gl::CreateProgram();
+        gl::AttachShader(program, vertex_shader);
+        gl::AttachShader(program, fragment_shader);
+        gl::BindAttribLocation(program, VERTEX_POSITION_LOCATION, c"aVertexPosition".as_ptr());
+        gl::LinkProgram(program);
+
+        program
+    }
+}
+
+/// Compile a single shader.
+unsafe fn compile_shader(kind: GLenum, source: &str) -> GLuint {
+    unsafe {
+        let shader = gl::CreateShader(kind);
+        gl::ShaderSource(
+            shader,
+            1,
+            [source.as_ptr()].as_ptr() as *const _,
+            &(source.len() as i32) as *const _,
+        );
+        gl::CompileShader(shader);
+        shader
+    }
+}
+
+/// OpenGL renderer errors.
+#[derive(thiserror::Error, Debug)]
+pub enum RendererError {
+    #[error("no working EGL config found")]
+    NoConfig,
+    #[error("EGL context creation failed: {0}")]
+    Context(#[source] glutin::error::Error),
+    #[error("EGL surface creation failed: {0}")]
+    Surface(#[source] glutin::error::Error),
+    #[error("could not make EGL context current: {0}")]
+    MakeCurrent(#[source] glutin::error::Error),
+    #[error("EGL buffer swap failed: {0}")]
+    SwapBuffers(#[source] glutin::error::Error),
+}
+
 /// OpenGL texture.
 #[derive(Debug)]
 pub struct Texture {
@@ -328,4 +562,38 @@ impl Texture {
             Self { id, width, height }
         }
     }
+
+    /// Delete the texture from OpenGL.
+    ///
+    /// # Safety
+    ///
+    /// This must be called with the texture's context made current.
+    pub unsafe fn delete(self) {
+        unsafe { gl::DeleteTextures(1, &self.id) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn damage_buffer_rects() {
+        let size = Size::new(200, 100);
+        let damage = [(Position::new(10, 20), Size::new(30, 40))];
+
+        // EGL's origin is in the bottom-left corner.
+        let rects = buffer_damage(&damage, size, Transform::Normal);
+        assert_eq!(rects, vec![Rect::new(10, 40, 30, 40)]);
+
+        // Damage is rotated with the buffer.
+        let rects = buffer_damage(&damage, size, Transform::_90);
+        assert_eq!(rects, vec![Rect::new(40, 160, 40, 30)]);
+
+        let rects = buffer_damage(&damage, size, Transform::_180);
+        assert_eq!(rects, vec![Rect::new(160, 20, 30, 40)]);
+
+        let rects = buffer_damage(&damage, size, Transform::Flipped270);
+        assert_eq!(rects, vec![Rect::new(20, 160, 40, 30)]);
+    }
 }
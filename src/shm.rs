@@ -0,0 +1,282 @@
+//! Shared memory image buffers.
+
+use chrono::{DateTime, Local};
+use image::{RgbaImage, imageops};
+use smithay_client_toolkit::reexports::client::protocol::wl_buffer::WlBuffer;
+use smithay_client_toolkit::reexports::client::protocol::wl_output::Transform;
+use smithay_client_toolkit::reexports::client::protocol::wl_shm::{Format, WlShm};
+use smithay_client_toolkit::reexports::client::{Dispatch, QueueHandle};
+use smithay_client_toolkit::registry::SimpleGlobal;
+use smithay_client_toolkit::shm::CreatePoolError;
+use smithay_client_toolkit::shm::raw::RawPool;
+
+use crate::cli::{Fit, Options, Rgb};
+use crate::geometry::Size;
+use crate::software::{self, Layers, SoftwareRenderer};
+use crate::surface::Surface;
+use crate::window::UnloadedImage;
+
+/// Static image rendered without OpenGL.
+///
+/// The image is scaled to cover the surface's physical size once and uploaded
+/// into a `wl_shm` buffer. The viewport's source rectangle is then used to
+/// crop the buffer around the focus point.
+///
+/// For rotated outputs, the buffer's pixels are rotated ahead of time to match
+/// the output's buffer transform.
+///
+/// The raw image data is dropped once it was uploaded, and only loaded again
+/// when the buffer has to be recreated.
+///
+/// With other layers like overlays, texts or patterns, or when the image does
+/// not cover the surface, all layers are instead composed on the CPU at the
+/// surface's physical size.
+pub struct ShmImage {
+    buffer: Option<ShmBuffer>,
+    image: Option<UnloadedImage>,
+    image_size: Option<Size>,
+    backdrop: Option<RgbaImage>,
+    overlays: Vec<RgbaImage>,
+    texts: Vec<RgbaImage>,
+    color: Rgb,
+    sky_time: DateTime<Local>,
+    transform: Transform,
+    shm: SimpleGlobal<WlShm, 1>,
+}
+
+impl ShmImage {
+    pub fn new(
+        shm: WlShm,
+        image: Option<UnloadedImage>,
+        overlays: Vec<RgbaImage>,
+        color: Rgb,
+        sky_time: DateTime<Local>,
+    ) -> Self {
+        Self {
+            image_size: image.as_ref().map(UnloadedImage::size),
+            image,
+            overlays,
+            color,
+            sky_time,
+            shm: SimpleGlobal::from_bound(shm),
+            buffer: Default::default(),
+            backdrop: Default::default(),
+            transform: Transform::Normal,
+            texts: Default::default(),
+        }
+    }
+
+    /// Replace the rasterized texts, discarding the existing buffer.
+    pub fn set_texts(&mut self, texts: Vec<RgbaImage>) {
+        self.texts = texts;
+        self.buffer = None;
+    }
+
+    /// Replace the image, discarding the existing buffer.
+    pub fn set_image(&mut self, image: UnloadedImage) {
+        self.image_size = Some(image.size());
+        self.image = Some(image);
+        self.buffer = None;
+    }
+
+    /// Replace the image's blurred backdrop, discarding the existing buffer.
+    pub fn set_backdrop(&mut self, backdrop: Option<RgbaImage>) {
+        self.backdrop = backdrop;
+        self.buffer = None;
+    }
+
+    /// Replace the background color, discarding the existing buffer.
+    pub fn set_color(&mut self, color: Rgb) {
+        if self.color != color {
+            self.color = color;
+            self.buffer = None;
+        }
+    }
+
+    /// Replace the time of day shown by the sky pattern, discarding the
+    /// existing buffer.
+    pub fn set_sky_time(&mut self, sky_time: DateTime<Local>) {
+        self.sky_time = sky_time;
+        self.buffer = None;
+    }
+
+    /// Replace the buffer transform, discarding the existing buffer.
+    pub fn set_transform(&mut self, transform: Transform) {
+        if self.transform != transform {
+            self.transform = transform;
+            self.buffer = None;
+        }
+    }
+
+    /// Discard the existing buffer, to redraw time-dependent content.
+    pub fn invalidate(&mut self) {
+        self.buffer = None;
+    }
+
+    /// Attach the image to a surface.
+    ///
+    /// The buffer is only recreated if the surface's physical size requires a
+    /// different pre-scaled image size. Since the image's raw data is dropped
+    /// after uploading it, `load_image` is used to load it again for the new
+    /// buffer.
+    pub fn attach<D>(
+        &mut self,
+        queue: &QueueHandle<D>,
+        surface: &Surface,
+        physical_size: Size,
+        scale: f64,
+        options: &Options,
+        load_image: impl FnOnce() -> Option<UnloadedImage>,
+    ) -> Result<(), CreatePoolError>
+    where
+        D: Dispatch<WlBuffer, ()> + 'static,
+    {
+        // Calculate the image's size when covering the entire surface.
+        let buffer_size = match self.standalone_size(options) {
+            Some(image_size) => {
+                let (_, size) = options.image_layout(physical_size, image_size);
+                Size::new(
+                    (size.width.round() as u32).max(physical_size.width),
+                    (size.height.round() as u32).max(physical_size.height),
+                )
+            },
+            None => physical_size,
+        };
+
+        // Upload the image, unless the existing buffer is already the right size.
+        let buffer = match self.buffer.take() {
+            Some(buffer) if buffer.size == buffer_size && buffer.scale == scale => buffer,
+            _ => {
+                if self.image.is_none() && self.image_size.is_some() {
+                    self.image = load_image();
+                }
+
+                let pixels = self.render(buffer_size, scale, options);
+                let pixels = transform_image(pixels, self.transform);
+
+                // Drop the image's raw data, since it is only needed for uploading.
+                self.image = None;
+
+                ShmBuffer::new(&self.shm, queue, &pixels, buffer_size, scale)?
+            },
+        };
+        let buffer = self.buffer.insert(buffer);
+
+        // Crop buffer to the visible region, distributing overflow around the focus.
+        let max_x = (buffer_size.width - physical_size.width) as f32;
+        let max_y = (buffer_size.height - physical_size.height) as f32;
+        let x = (max_x * options.focus.x).round().clamp(0., max_x);
+        let y = (max_y * options.focus.y).round().clamp(0., max_y);
+        let (width, height) = (physical_size.width as f64, physical_size.height as f64);
+        surface.viewport().set_source(x as f64, y as f64, width, height);
+
+        surface.wl_surface().attach(Some(&buffer.buffer), 0, 0);
+
+        Ok(())
+    }
+
+    /// Render the buffer's content.
+    fn render(&self, size: Size, scale: f64, options: &Options) -> RgbaImage {
+        // Without other layers, the image is cropped by the viewport instead.
+        let standalone = self.image.as_ref().filter(|_| self.standalone_size(options).is_some());
+        if let Some(image) = standalone {
+            let mut image = image.resize(size);
+            composite_over(&mut image, self.color);
+            return image;
+        }
+
+        let image = self.image.as_ref().map(UnloadedImage::to_rgba);
+        let layers = Layers {
+            image: image.as_ref(),
+            backdrop: self.backdrop.as_ref(),
+            overlays: &self.overlays,
+            texts: &self.texts,
+            color: self.color,
+            sky_time: self.sky_time,
+        };
+        let mut renderer = SoftwareRenderer::new(size);
+        software::render(&mut renderer, size, scale, layers, options);
+        renderer.into_image()
+    }
+
+    /// Get the image's size, if it covers the surface without any other layers.
+    fn standalone_size(&self, options: &Options) -> Option<Size> {
+        let layered = !self.overlays.is_empty()
+            || !self.texts.is_empty()
+            || options.pattern.is_some()
+            || options.fit != Fit::Cover;
+        self.image_size.filter(|_| !layered)
+    }
+}
+
+/// Pre-scaled image buffer.
+struct ShmBuffer {
+    buffer: WlBuffer,
+    /// Size before the buffer transform.
+    size: Size,
+    scale: f64,
+
+    // Keep the pool alive as long as its buffer.
+    _pool: RawPool,
+}
+
+impl ShmBuffer {
+    fn new<D>(
+        shm: &SimpleGlobal<WlShm, 1>,
+        queue: &QueueHandle<D>,
+        pixels: &RgbaImage,
+        size: Size,
+        scale: f64,
+    ) -> Result<Self, CreatePoolError>
+    where
+        D: Dispatch<WlBuffer, ()> + 'static,
+    {
+        let (width, height) = pixels.dimensions();
+        let stride = width as usize * 4;
+        let mut pool = RawPool::new(stride * height as usize, shm)?;
+
+        // Write image in little-endian XRGB.
+        for (dst, pixel) in pool.mmap().chunks_exact_mut(4).zip(pixels.pixels()) {
+            let [r, g, b, _] = pixel.0;
+            dst.copy_from_slice(&[b, g, r, u8::MAX]);
+        }
+
+        let (width, height) = (width as i32, height as i32);
+        let buffer =
+            pool.create_buffer(0, width, height, stride as i32, Format::Xrgb8888, (), queue);
+
+        Ok(Self { buffer, size, scale, _pool: pool })
+    }
+}
+
+impl Drop for ShmBuffer {
+    fn drop(&mut self) {
+        self.buffer.destroy();
+    }
+}
+
+/// Blend translucent pixels over an opaque background color.
+fn composite_over(image: &mut RgbaImage, color: Rgb) {
+    for pixel in image.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        let blend = |src: u8, dst: u8| {
+            let (src, dst, a) = (src as u32, dst as u32, a as u32);
+            ((src * a + dst * (255 - a) + 127) / 255) as u8
+        };
+        pixel.0 = [blend(r, color.r), blend(g, color.g), blend(b, color.b), u8::MAX];
+    }
+}
+
+/// Rotate surface pixels into the buffer's orientation.
+fn transform_image(image: RgbaImage, transform: Transform) -> RgbaImage {
+    match transform {
+        Transform::_90 => imageops::rotate90(&image),
+        Transform::_180 => imageops::rotate180(&image),
+        Transform::_270 => imageops::rotate270(&image),
+        Transform::Flipped => imageops::flip_horizontal(&image),
+        Transform::Flipped90 => imageops::flip_vertical(&imageops::rotate90(&image)),
+        Transform::Flipped180 => imageops::flip_vertical(&image),
+        Transform::Flipped270 => imageops::flip_vertical(&imageops::rotate270(&image)),
+        _ => image,
+    }
+}
@@ -0,0 +1,222 @@
+//! CPU renderer.
+
+use chrono::{DateTime, Local};
+use image::{ImageFormat, Rgba, RgbaImage};
+
+use crate::cli::{Fit, Options, PatternKind, RenderOptions, Rgb};
+use crate::geometry::{Orientation, Position, Size};
+use crate::text::TextLayer;
+use crate::window::UnloadedImage;
+use crate::{Error, backdrop, palette, pattern};
+
+/// Render the wallpaper to a PNG file.
+pub fn render_to_file(options: &Options, render_options: &RenderOptions) -> Result<(), Error> {
+    let options = &options.oriented(Orientation::of(render_options.size));
+    let stream = UnloadedImage::read_stream(options)?;
+    let image = UnloadedImage::from_options(options, stream.as_deref())?;
+    let color = palette::background(options.color, image.as_ref());
+    let backdrop = backdrop::create(options, image.as_ref());
+    let image = image.map(|image| image.to_rgba());
+    let overlays = options
+        .overlays
+        .iter()
+        .map(|overlay| Ok(UnloadedImage::new(&overlay.path)?.to_rgba()))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let scale = render_options.scale;
+    let texts = options
+        .texts
+        .iter()
+        .map(|text| Ok(TextLayer::new(text)?.rasterize(text, scale)))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let layers = Layers {
+        image: image.as_ref(),
+        backdrop: backdrop.as_ref(),
+        overlays: &overlays,
+        texts: &texts,
+        color,
+        sky_time: Local::now(),
+    };
+    let physical_size = render_options.size * scale;
+    let mut renderer = SoftwareRenderer::new(physical_size);
+    render(&mut renderer, physical_size, scale, layers, options);
+
+    renderer.into_image().save_with_format(&render_options.output, ImageFormat::Png)?;
+
+    Ok(())
+}
+
+/// Render the wallpaper.
+///
+/// This mirrors the layout and shading of `Window::gl_render`, so the result
+/// matches what is presented on a surface with the same physical size.
+pub fn render(
+    renderer: &mut SoftwareRenderer,
+    physical_size: Size,
+    scale: f64,
+    layers: Layers<'_>,
+    options: &Options,
+) {
+    // Render background color.
+    let [r, g, b] =
+        [layers.color.r as f32 / 255., layers.color.g as f32 / 255., layers.color.b as f32 / 255.];
+    renderer.clear([r, g, b, 1.]);
+
+    // Render procedural pattern.
+    if let Some(pattern) = &options.pattern {
+        let colors = pattern::colors(pattern, layers.color, &layers.sky_time);
+        renderer.draw_pattern(pattern.kind, colors, pattern.size * scale as f32);
+    }
+
+    // Render blurred copy of the image around it.
+    if let Some(backdrop) = layers.backdrop {
+        let backdrop_size = Size::new(backdrop.width(), backdrop.height());
+        let (position, size) = Fit::Cover.layout(physical_size, backdrop_size, options.focus);
+        renderer.draw_texture_at(backdrop, position, size, 1.);
+    }
+
+    // Render wallpaper image, fit to screen dimensions.
+    if let Some(image) = layers.image {
+        let image_size = Size::new(image.width(), image.height());
+        let (position, size) = options.image_layout(physical_size, image_size);
+
+        renderer.draw_texture_at(image, position, size, 1.);
+    }
+
+    // Render overlays on top.
+    for (overlay, image) in options.overlays.iter().zip(layers.overlays) {
+        let image_size = Size::new(image.width(), image.height());
+        let (position, size) = overlay.layout(physical_size, scale, image_size);
+        renderer.draw_texture_at(image, position, size, overlay.opacity);
+    }
+
+    // Render texts at their rasterized size.
+    for (text, image) in options.texts.iter().zip(layers.texts) {
+        let image_size = Size::new(image.width(), image.height());
+        let position = text.layout(physical_size, scale, image_size);
+        renderer.draw_texture_at(image, position, image_size.into(), 1.);
+    }
+}
+
+/// Decoded wallpaper layers.
+///
+/// The `overlays` and `texts` must match `options.overlays` and
+/// `options.texts`, with texts rasterized at the surface's scale. The `color`
+/// is the background color, with automatic colors already resolved, and the
+/// `backdrop` is the image's blurred copy created by [`backdrop::create`].
+#[derive(Copy, Clone, Default)]
+pub struct Layers<'a> {
+    pub image: Option<&'a RgbaImage>,
+    pub backdrop: Option<&'a RgbaImage>,
+    pub overlays: &'a [RgbaImage],
+    pub texts: &'a [RgbaImage],
+    pub color: Rgb,
+    /// Time of day shown by the sky pattern.
+    pub sky_time: DateTime<Local>,
+}
+
+/// CPU renderer.
+///
+/// This emulates the subset of OpenGL ES used by the GPU renderer, including
+/// pixel center rasterization and bilinear, edge-clamped texture sampling.
+pub struct SoftwareRenderer {
+    buffer: RgbaImage,
+}
+
+impl SoftwareRenderer {
+    /// Initialize a new renderer.
+    pub fn new(size: Size) -> Self {
+        Self { buffer: RgbaImage::new(size.width, size.height) }
+    }
+
+    /// Fill the entire buffer with a color.
+    pub fn clear(&mut self, color: [f32; 4]) {
+        let color = Rgba(color.map(|channel| (channel * 255.).round() as u8));
+        self.buffer.pixels_mut().for_each(|pixel| *pixel = color);
+    }
+
+    /// Fill the entire buffer with a procedural pattern.
+    ///
+    /// The `cell_size` is the pattern's scale in physical pixels.
+    pub fn draw_pattern(&mut self, kind: PatternKind, colors: [[f32; 3]; 2], cell_size: f32) {
+        let height = self.buffer.height() as f32;
+        for (x, y, pixel) in self.buffer.enumerate_pixels_mut() {
+            let color =
+                pattern::sample(kind, colors, cell_size, height, x as f32 + 0.5, y as f32 + 0.5);
+            let [r, g, b] = color.map(|channel| (channel * 255.).round() as u8);
+            *pixel = Rgba([r, g, b, pixel[3]]);
+        }
+    }
+
+    /// Render texture at a position in viewport-coordinates.
+    ///
+    /// The texture is blended with the existing content based on its alpha
+    /// channel, multiplied by `opacity`.
+    pub fn draw_texture_at(
+        &mut self,
+        texture: &RgbaImage,
+        position: Position<f32>,
+        size: Size<f32>,
+        opacity: f32,
+    ) {
+        if size.width <= 0. || size.height <= 0. || texture.width() == 0 || texture.height() == 0 {
+            return;
+        }
+
+        // Find all pixels whose center is covered by the texture.
+        let buffer_size = Size::new(self.buffer.width() as f32, self.buffer.height() as f32);
+        let start_x = (position.x - 0.5).ceil().clamp(0., buffer_size.width) as u32;
+        let start_y = (position.y - 0.5).ceil().clamp(0., buffer_size.height) as u32;
+        let end_x = (position.x + size.width - 0.5).ceil().clamp(0., buffer_size.width) as u32;
+        let end_y = (position.y + size.height - 0.5).ceil().clamp(0., buffer_size.height) as u32;
+
+        for y in start_y..end_y {
+            let v = (y as f32 + 0.5 - position.y) / size.height;
+            for x in start_x..end_x {
+                let u = (x as f32 + 0.5 - position.x) / size.width;
+                let pixel = self.buffer.get_pixel_mut(x, y);
+                *pixel = blend(*pixel, sample(texture, u, v), opacity);
+            }
+        }
+    }
+
+    /// Get the rendered image.
+    pub fn into_image(self) -> RgbaImage {
+        self.buffer
+    }
+}
+
+/// Blend `src` over `dst`, keeping the destination's alpha.
+fn blend(dst: Rgba<u8>, src: Rgba<u8>, opacity: f32) -> Rgba<u8> {
+    let alpha = src[3] as f32 / 255. * opacity;
+    let [r, g, b] = std::array::from_fn(|i| {
+        (src[i] as f32 * alpha + dst[i] as f32 * (1. - alpha)).round() as u8
+    });
+    Rgba([r, g, b, dst[3]])
+}
+
+/// Sample a texture using bilinear filtering and edge clamping.
+fn sample(texture: &RgbaImage, u: f32, v: f32) -> Rgba<u8> {
+    let max_x = texture.width() - 1;
+    let max_y = texture.height() - 1;
+
+    // Convert to texel space, where texel centers are at integer coordinates.
+    let x = (u * texture.width() as f32 - 0.5).clamp(0., max_x as f32);
+    let y = (v * texture.height() as f32 - 0.5).clamp(0., max_y as f32);
+
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = ((x0 + 1).min(max_x), (y0 + 1).min(max_y));
+    let (x_fract, y_fract) = (x.fract(), y.fract());
+
+    let top_left = texture.get_pixel(x0, y0).0;
+    let top_right = texture.get_pixel(x1, y0).0;
+    let bottom_left = texture.get_pixel(x0, y1).0;
+    let bottom_right = texture.get_pixel(x1, y1).0;
+
+    Rgba(std::array::from_fn(|i| {
+        let top = top_left[i] as f32 * (1. - x_fract) + top_right[i] as f32 * x_fract;
+        let bottom = bottom_left[i] as f32 * (1. - x_fract) + bottom_right[i] as f32 * x_fract;
+        (top * (1. - y_fract) + bottom * y_fract).round() as u8
+    }))
+}
@@ -0,0 +1,218 @@
+//! Persistent wallpaper state.
+
+use std::path::{Path, PathBuf};
+use std::{env, fs, io, process};
+
+use tracing::warn;
+
+use crate::cli::{Color, Options, Rgb};
+use crate::geometry::Position;
+
+/// Layer shell namespace using the unqualified state file.
+const DEFAULT_NAMESPACE: &str = "wallpaper";
+
+/// State file of a layer shell namespace.
+pub struct StateFile {
+    path: Option<PathBuf>,
+    saved: Option<SavedState>,
+}
+
+impl StateFile {
+    pub fn new(namespace: &str) -> Self {
+        let path = state_path(namespace);
+        let saved = path.as_deref().and_then(SavedState::read);
+        Self { path, saved }
+    }
+
+    /// Persist the displayed image, color and focus, unless they are unchanged.
+    ///
+    /// Automatic colors are saved as the `color` resolved for the image.
+    pub fn update(&mut self, options: &Options, color: Rgb) {
+        let mut state = SavedState::from_options(options);
+        state.color = Color::Rgb(color);
+        if self.saved.as_ref() == Some(&state) {
+            return;
+        }
+
+        let result = self.path.as_deref().ok_or(io::ErrorKind::NotFound.into());
+        match result.and_then(|path| state.write(path)) {
+            Ok(()) => self.saved = Some(state),
+            Err(err) => warn!("Could not save wallpaper state: {err}"),
+        }
+    }
+}
+
+/// Wallpaper state restored with `--restore`.
+#[derive(PartialEq, Debug)]
+pub struct SavedState {
+    pub image: Option<PathBuf>,
+    pub color: Color,
+    pub focus: Position<f32>,
+}
+
+impl SavedState {
+    /// Get the state of the current options.
+    ///
+    /// Images which are not read from the filesystem are not persisted.
+    pub fn from_options(options: &Options) -> Self {
+        // Resolve relative paths, since the working directory might change.
+        let image = options
+            .image_path()
+            .map(|path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+
+        Self { image, color: options.color, focus: options.focus }
+    }
+
+    /// Load the last state saved for a layer shell namespace.
+    pub fn load(namespace: &str) -> Option<Self> {
+        Self::read(&state_path(namespace)?)
+    }
+
+    /// Read the state from a file.
+    fn read(path: &Path) -> Option<Self> {
+        Self::parse(&fs::read_to_string(path).ok()?)
+    }
+
+    /// Write the state to a file.
+    fn write(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Replace the file atomically, to never leave a partial state behind.
+        //
+        // Each process uses its own temporary file, since wallpapers for
+        // multiple outputs might share the same namespace.
+        let tmp_path = path.with_extension(format!("{}.tmp", process::id()));
+        fs::write(&tmp_path, self.serialize())?;
+        fs::rename(&tmp_path, path)
+    }
+
+    /// Apply the state to options which were not set explicitly.
+    pub fn restore(self, options: &mut Options, is_explicit: impl Fn(&str) -> bool) {
+        let has_image = is_explicit("image") || is_explicit("image_fd");
+        if let Some(image) = self.image.filter(|_| !has_image) {
+            options.image = Some(image);
+        }
+        if !is_explicit("color") {
+            options.color = self.color;
+        }
+        if !is_explicit("focus") {
+            options.focus = self.focus;
+        }
+    }
+
+    /// Parse the state file's `KEY=VALUE` lines.
+    fn parse(content: &str) -> Option<Self> {
+        let mut state = Self::from_options(&Options::default());
+        for line in content.lines() {
+            let (key, value) = line.split_once('=')?;
+            match key {
+                "image" => state.image = Some(value.into()),
+                "color" => state.color = value.parse().ok()?,
+                "focus" => state.focus = value.parse().ok()?,
+                // Ignore unknown keys, for compatibility with newer versions.
+                _ => (),
+            }
+        }
+        Some(state)
+    }
+
+    /// Convert the state to `KEY=VALUE` lines.
+    fn serialize(&self) -> String {
+        let mut content = format!("color={}\n", self.color);
+        content.push_str(&format!("focus={}+{}\n", self.focus.x, self.focus.y));
+        if let Some(image) = &self.image {
+            content.push_str(&format!("image={}\n", image.display()));
+        }
+        content
+    }
+}
+
+/// Path of the state file for a layer shell namespace.
+fn state_path(namespace: &str) -> Option<PathBuf> {
+    let state_home = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| Some(PathBuf::from(env::var_os("HOME")?).join(".local/state")))?;
+    Some(state_home.join("tabula").join(state_file_name(namespace)))
+}
+
+/// Name of the state file for a layer shell namespace.
+///
+/// Characters which are not safe in file names are replaced with `_`.
+fn state_file_name(namespace: &str) -> String {
+    if namespace == DEFAULT_NAMESPACE {
+        return "state".into();
+    }
+
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.');
+    let namespace: String = namespace.chars().map(|c| if is_safe(c) { c } else { '_' }).collect();
+    format!("state-{namespace}")
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::cli::Rgb;
+
+    #[test]
+    fn roundtrip() {
+        let state = SavedState {
+            image: Some("/tmp/wallpaper.png".into()),
+            color: Color::Rgb(Rgb { r: 0x33, g: 0x66, b: 0x99 }),
+            focus: Position::new(0.25, 1.),
+        };
+        assert_eq!(SavedState::parse(&state.serialize()), Some(state));
+
+        let state = SavedState { image: None, ..SavedState::parse("").unwrap() };
+        assert_eq!(SavedState::parse(&state.serialize()), Some(state));
+
+        assert_eq!(SavedState::parse("color=#zzzzzz"), None);
+    }
+
+    #[test]
+    fn unchanged_state_not_saved() {
+        let directory = env::temp_dir().join(format!("tabula-state-{}", process::id()));
+        let path = directory.join("state");
+        let mut state_file = StateFile { path: Some(path.clone()), saved: None };
+
+        let mut options = Options::default();
+        let color = Rgb { r: 0, g: 0, b: 0 };
+        state_file.update(&options, color);
+        assert!(path.exists());
+
+        // Identical states are not written again.
+        fs::remove_file(&path).unwrap();
+        state_file.update(&options, color);
+        assert!(!path.exists());
+
+        options.focus = Position::new(0., 0.);
+        state_file.update(&options, color);
+        assert_eq!(SavedState::read(&path).unwrap().focus, Position::new(0., 0.));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn namespaced_file_names() {
+        assert_eq!(state_file_name("wallpaper"), "state");
+        assert_eq!(state_file_name("lockscreen"), "state-lockscreen");
+        assert_eq!(state_file_name("../panel 2"), "state-.._panel_2");
+    }
+
+    #[test]
+    fn restore_precedence() {
+        let state = SavedState::parse("image=/tmp/saved.png\ncolor=#ff0000\nfocus=0+1").unwrap();
+
+        // Explicit options take precedence over the saved state.
+        let mut options = Options::parse_from(["tabula", "-c", "#0000ff", "--image-fd", "3"]);
+        state.restore(&mut options, |id| id == "color" || id == "image_fd");
+
+        assert_eq!(options.image, None);
+        assert_eq!(options.color, Color::Rgb(Rgb { r: 0, g: 0, b: 255 }));
+        assert_eq!(options.focus, Position::new(0., 1.));
+    }
+}
@@ -0,0 +1,94 @@
+//! Layer shell surface lifecycle.
+
+use smithay_client_toolkit::reexports::client::QueueHandle;
+use smithay_client_toolkit::reexports::client::protocol::wl_surface::WlSurface;
+use smithay_client_toolkit::reexports::protocols::wp::fractional_scale::v1::client::wp_fractional_scale_v1::WpFractionalScaleV1;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewport::WpViewport;
+use smithay_client_toolkit::shell::WaylandSurface;
+use smithay_client_toolkit::shell::wlr_layer::{Anchor, Layer, LayerSurface};
+
+use crate::cli::{self, Options};
+use crate::wayland::{ProtocolStates, WallpaperState};
+
+/// Layer shell surface, with the Wayland objects attached to it.
+///
+/// All objects are destroyed with the surface, so a closed surface can be
+/// replaced by creating a new one.
+pub struct Surface {
+    layer: LayerSurface,
+    viewport: WpViewport,
+    fractional_scale: Option<WpFractionalScaleV1>,
+}
+
+impl Surface {
+    /// Create the layer shell surface configured in the options.
+    ///
+    /// The surface is committed without a buffer, to request its first
+    /// configure.
+    pub fn new<D: WallpaperState>(
+        protocol_states: &ProtocolStates,
+        queue: &QueueHandle<D>,
+        options: &Options,
+    ) -> Self {
+        // Create surface's Wayland global handles.
+        let surface = protocol_states.compositor.create_surface(queue);
+        let fractional_scale = protocol_states
+            .fractional_scale
+            .as_ref()
+            .map(|fractional_scale| fractional_scale.fractional_scaling(queue, &surface));
+        let viewport = protocol_states.viewporter.viewport(queue, &surface);
+
+        // Create the layer shell window.
+        let layer = match options.layer {
+            cli::Layer::Background => Layer::Background,
+            cli::Layer::Bottom => Layer::Bottom,
+            cli::Layer::Top => Layer::Top,
+            cli::Layer::Overlay => Layer::Overlay,
+        };
+        let namespace = Some(options.namespace.as_str());
+        let layer = protocol_states
+            .layer_shell
+            .create_layer_surface(queue, surface, layer, namespace, None);
+
+        let edges = [
+            (options.anchor.top, Anchor::TOP),
+            (options.anchor.bottom, Anchor::BOTTOM),
+            (options.anchor.left, Anchor::LEFT),
+            (options.anchor.right, Anchor::RIGHT),
+        ];
+        let anchor = edges.into_iter().filter(|(anchored, _)| *anchored).map(|(_, edge)| edge);
+        layer.set_anchor(anchor.collect());
+
+        let margin = options.margin;
+        layer.set_margin(margin.top, margin.right, margin.bottom, margin.left);
+        layer.set_exclusive_zone(options.exclusive_zone);
+        layer.set_size(options.surface_size.width, options.surface_size.height);
+        layer.commit();
+
+        Self { layer, viewport, fractional_scale }
+    }
+
+    /// Get the layer shell surface.
+    pub fn layer(&self) -> &LayerSurface {
+        &self.layer
+    }
+
+    /// Get the underlying Wayland surface.
+    pub fn wl_surface(&self) -> &WlSurface {
+        self.layer.wl_surface()
+    }
+
+    /// Get the surface's viewport.
+    pub fn viewport(&self) -> &WpViewport {
+        &self.viewport
+    }
+}
+
+impl Drop for Surface {
+    fn drop(&mut self) {
+        self.viewport.destroy();
+        if let Some(fractional_scale) = &self.fractional_scale {
+            fractional_scale.destroy();
+        }
+    }
+}
@@ -0,0 +1,1367 @@
+//! Mock compositor integration tests.
+//!
+//! An in-process compositor records the protocol state committed by Tabula's
+//! surface, so tests can assert on the traffic without a real compositor.
+//!
+//! Only configurations which do not require OpenGL are exercised, since EGL
+//! cannot be initialized on the mock connection.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::iter;
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use clap::Parser;
+use smithay_client_toolkit::reexports::client::{Connection, EventQueue, globals};
+use wayland_protocols::wp::fractional_scale::v1::server::{
+    wp_fractional_scale_manager_v1, wp_fractional_scale_v1,
+};
+use wayland_protocols::wp::single_pixel_buffer::v1::server::wp_single_pixel_buffer_manager_v1;
+use wayland_protocols::wp::viewporter::server::{wp_viewport, wp_viewporter};
+use wayland_protocols_wlr::layer_shell::v1::server::{zwlr_layer_shell_v1, zwlr_layer_surface_v1};
+use wayland_server::backend::{ClientData, GlobalId, ObjectId};
+use wayland_server::protocol::{
+    wl_buffer, wl_callback, wl_compositor, wl_output, wl_region, wl_shm, wl_shm_pool, wl_surface,
+};
+use wayland_server::{
+    Client, DataInit, Dispatch, Display, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+};
+use wl_output::Transform;
+use wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1;
+use wp_fractional_scale_v1::WpFractionalScaleV1;
+use wp_single_pixel_buffer_manager_v1::WpSinglePixelBufferManagerV1;
+use zwlr_layer_shell_v1::{Layer, ZwlrLayerShellV1};
+use zwlr_layer_surface_v1::{Anchor, ZwlrLayerSurfaceV1};
+
+use crate::cli::Options;
+use crate::{Error, State};
+
+#[test]
+fn layer_surface_setup() {
+    let mut harness = Harness::new(Globals::default(), &[]);
+
+    let surface = harness.surface();
+    let layer = surface.current.layer.expect("missing layer surface");
+    assert_eq!(layer.layer, Some(Layer::Background));
+    assert_eq!(layer.namespace, "wallpaper");
+    assert_eq!(layer.anchor, Anchor::Top | Anchor::Bottom | Anchor::Left | Anchor::Right);
+    assert_eq!(layer.exclusive_zone, -1);
+    assert_eq!(layer.size, (0, 0));
+    assert_eq!(layer.margin, (0, 0, 0, 0));
+
+    // The initial commit must not attach a buffer.
+    assert_eq!(surface.commits, 1);
+    assert_eq!(surface.current.buffer, None);
+    assert!(surface.fractional_scale.is_some());
+    assert!(!harness.state.terminated);
+}
+
+#[test]
+fn layer_surface_options() {
+    let args = [
+        "--layer",
+        "bottom",
+        "--namespace",
+        "panel-backdrop",
+        "--anchor",
+        "top,left,right",
+        "--surface-size",
+        "0x32",
+        "--margin",
+        "4,8,0,8",
+        "--exclusive-zone",
+        "36",
+    ];
+    let mut harness = Harness::new(Globals::default(), &args);
+
+    let layer = harness.surface().current.layer.expect("missing layer surface");
+    assert_eq!(layer.layer, Some(Layer::Bottom));
+    assert_eq!(layer.namespace, "panel-backdrop");
+    assert_eq!(layer.anchor, Anchor::Top | Anchor::Left | Anchor::Right);
+    assert_eq!(layer.exclusive_zone, 36);
+    assert_eq!(layer.size, (0, 32));
+    assert_eq!(layer.margin, (4, 8, 0, 8));
+
+    // Contradicting options are rejected.
+    match Harness::try_new(Globals::default(), &["--anchor", "top"]) {
+        Err(Error::InvalidOptions(_)) => (),
+        Err(err) => panic!("unexpected error: {err}"),
+        Ok(_) => panic!("state creation succeeded without surface size"),
+    }
+}
+
+#[test]
+fn single_pixel_buffer_color() {
+    let mut harness = Harness::new(Globals::default(), &["-c", "#FF3300"]);
+    harness.configure(360, 780);
+
+    let surface = harness.surface();
+    assert_eq!(surface.acked_serial, Some(1));
+    assert_eq!(surface.current.viewport_destination, Some((360, 780)));
+    assert_eq!(surface.current.opaque_region, Some(vec![(0, 0, 360, 780)]));
+    assert_eq!(surface.current.damage, vec![(0, 0, 360, 780)]);
+
+    let step = u32::MAX / 255;
+    let expected = Buffer::SinglePixel { r: u32::MAX, g: 0x33 * step, b: 0, a: u32::MAX };
+    assert_eq!(surface.current.buffer, Some(expected));
+}
+
+#[test]
+fn resize() {
+    let mut harness = Harness::new(Globals::default(), &[]);
+    harness.configure(360, 780);
+    harness.configure(780, 360);
+
+    let surface = harness.surface();
+    assert_eq!(surface.acked_serial, Some(2));
+    assert_eq!(surface.current.viewport_destination, Some((780, 360)));
+    assert_eq!(surface.current.opaque_region, Some(vec![(0, 0, 780, 360)]));
+
+    // Identical configures must not cause a redraw.
+    let commits = surface.commits;
+    harness.configure(780, 360);
+    assert_eq!(harness.surface().commits, commits);
+}
+
+#[test]
+fn static_content_requests_no_frames() {
+    let mut harness = Harness::new(Globals::default(), &[]);
+    harness.configure(360, 780);
+    harness.configure(780, 360);
+
+    let surface = harness.surface();
+    assert_eq!(surface.frame_requests, 0);
+    assert_eq!(surface.commits, 3);
+}
+
+#[test]
+fn closed() {
+    let mut harness = Harness::new(Globals::default(), &[]);
+    harness.configure(360, 780);
+
+    harness.with_server(|server| server.surface().layer_surface.as_ref().unwrap().closed());
+    harness.roundtrip();
+
+    assert!(harness.state.terminated);
+}
+
+#[test]
+fn reopen_after_closed() {
+    let globals = Globals { output_scales: &[1], ..Globals::default() };
+    let mut harness = Harness::new(globals, &["--reopen", "-c", "#FF3300"]);
+    harness.configure(360, 780);
+
+    harness.with_server(|server| server.surface().layer_surface.as_ref().unwrap().closed());
+    harness.roundtrip();
+
+    // A new surface replaces the closed one, and is drawn once configured.
+    assert!(!harness.state.terminated);
+    assert!(!harness.state.wallpaper.is_closed());
+    let surface = harness.surface();
+    assert_eq!(surface.commits, 1);
+    assert_eq!(surface.current.buffer, None);
+
+    harness.configure(360, 780);
+    assert!(harness.surface().current.buffer.is_some());
+
+    // Without any outputs, the surface is only recreated once one is added.
+    let mut harness = Harness::new(Globals::default(), &["--reopen"]);
+    harness.configure(360, 780);
+    harness.with_server(|server| server.surface().layer_surface.as_ref().unwrap().closed());
+    harness.roundtrip();
+
+    assert!(!harness.state.terminated);
+    assert!(harness.state.wallpaper.is_closed());
+}
+
+#[test]
+fn reopen_after_output_removal() {
+    let remove_closed_output = |harness: &mut Harness| {
+        harness.with_display(|server, display| {
+            server.surface().layer_surface.as_ref().unwrap().closed();
+            display.remove_global::<Server>(server.output_globals[0].clone());
+        });
+        harness.roundtrip();
+    };
+
+    // The surface is not recreated while its output is being removed.
+    let globals = Globals { output_scales: &[1], ..Globals::default() };
+    let mut harness = Harness::new(globals, &["--reopen"]);
+    harness.configure(360, 780);
+    remove_closed_output(&mut harness);
+
+    assert!(!harness.state.terminated);
+    assert!(harness.state.wallpaper.is_closed());
+
+    // Remaining outputs can show the new surface.
+    let globals = Globals { output_scales: &[1, 1], ..Globals::default() };
+    let mut harness = Harness::new(globals, &["--reopen"]);
+    harness.configure(360, 780);
+    remove_closed_output(&mut harness);
+
+    assert!(!harness.state.wallpaper.is_closed());
+    assert_eq!(harness.surface().commits, 1);
+}
+
+#[test]
+fn output_scale() {
+    let globals = Globals {
+        compositor_version: 5,
+        output_scales: &[1, 2],
+        single_pixel_buffer: false,
+        fractional_scale: false,
+        ..Globals::default()
+    };
+    let mut harness = Harness::new(globals, &["--shm"]);
+    harness.configure(100, 50);
+
+    let mut enter_output = |index: usize, entered: bool| {
+        harness.with_server(|server| {
+            let output = server.outputs[index].clone();
+            match entered {
+                true => server.surface().resource.enter(&output),
+                false => server.surface().resource.leave(&output),
+            }
+        });
+        harness.roundtrip();
+        harness.surface().current.buffer.unwrap().shm_size()
+    };
+
+    assert_eq!(enter_output(0, true), Some((100, 50)));
+
+    // Surfaces straddling outputs use the highest scale.
+    assert_eq!(enter_output(1, true), Some((200, 100)));
+    assert_eq!(enter_output(0, false), Some((200, 100)));
+    assert_eq!(enter_output(0, true), Some((200, 100)));
+    assert_eq!(enter_output(1, false), Some((100, 50)));
+}
+
+#[test]
+fn preferred_buffer_scale() {
+    let globals = Globals {
+        output_scales: &[2],
+        single_pixel_buffer: false,
+        fractional_scale: false,
+        ..Globals::default()
+    };
+    let mut harness = Harness::new(globals, &["--shm"]);
+    harness.configure(100, 50);
+
+    // The compositor's preferred scale takes precedence over output scales.
+    harness.with_server(|server| {
+        let output = server.outputs[0].clone();
+        server.surface().resource.enter(&output);
+        server.surface().resource.preferred_buffer_scale(3);
+    });
+    harness.roundtrip();
+
+    let buffer = harness.surface().current.buffer.unwrap();
+    assert_eq!(buffer.shm_size(), Some((300, 150)));
+}
+
+#[test]
+fn missing_layer_shell() {
+    let globals = Globals { layer_shell: false, ..Globals::default() };
+    match Harness::try_new(globals, &[]) {
+        Err(Error::WaylandProtocol(global, _)) => assert_eq!(global, "wlr_layer_shell"),
+        Err(err) => panic!("unexpected error: {err}"),
+        Ok(_) => panic!("state creation succeeded without layer shell"),
+    }
+}
+
+#[cfg(feature = "png")]
+mod shm {
+    use std::os::fd::AsRawFd;
+
+    use image::{DynamicImage, Rgb, RgbImage, Rgba, RgbaImage};
+
+    use super::*;
+
+    #[test]
+    fn shm_buffer_crop() {
+        let image = TestImage::new("shm_buffer_crop", 200, 100);
+        let mut harness = Harness::new(Globals::default(), &["--shm", "-i", image.path()]);
+        harness.configure(100, 100);
+
+        // Image is scaled to cover the surface and cropped around the focus point.
+        let surface = harness.surface();
+        assert_eq!(surface.current.viewport_destination, Some((100, 100)));
+        assert_eq!(surface.current.viewport_source, Some((50., 0., 100., 100.)));
+        let buffer = surface.current.buffer.as_ref().unwrap();
+        assert_eq!(buffer.shm_size(), Some((200, 100)));
+
+        // Left half of the image is red, right half is blue.
+        assert_eq!(buffer.shm_pixel(10, 50), Some([255, 0, 0, 255]));
+        assert_eq!(buffer.shm_pixel(190, 50), Some([0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn shm_translucent_image() {
+        let image = TestImage::translucent("shm_translucent_image", 100, 100);
+        let args = ["--shm", "-c", "#00ff00", "-i", image.path()];
+        let mut harness = Harness::new(Globals::default(), &args);
+        harness.configure(100, 100);
+
+        // Transparent pixels show the background color.
+        let surface = harness.surface();
+        let buffer = surface.current.buffer.as_ref().unwrap();
+        assert_eq!(buffer.shm_pixel(10, 50), Some([255, 0, 0, 255]));
+        assert_eq!(buffer.shm_pixel(90, 50), Some([0, 255, 0, 255]));
+    }
+
+    #[test]
+    fn fractional_scale_change() {
+        let image = TestImage::new("fractional_scale_change", 200, 100);
+        let args = ["--shm", "-i", image.path(), "-f", "0+0"];
+        let mut harness = Harness::new(Globals::default(), &args);
+        harness.configure(100, 100);
+
+        harness.with_server(|server| {
+            let surface = server.surface();
+            surface.fractional_scale.as_ref().unwrap().preferred_scale(180);
+        });
+        harness.roundtrip();
+
+        let surface = harness.surface();
+        assert_eq!(surface.current.viewport_destination, Some((100, 100)));
+        assert_eq!(surface.current.viewport_source, Some((0., 0., 150., 150.)));
+        let buffer = surface.current.buffer.as_ref().unwrap();
+        assert_eq!(buffer.shm_size(), Some((300, 150)));
+    }
+
+    #[test]
+    fn scale_changes_coalesced() {
+        let image = TestImage::new("scale_changes_coalesced", 100, 100);
+        let mut harness = Harness::new(Globals::default(), &["--shm", "-i", image.path()]);
+        harness.configure(100, 100);
+
+        harness.with_server(|server| {
+            let fractional_scale = server.surface().fractional_scale.clone().unwrap();
+            fractional_scale.preferred_scale(180);
+            fractional_scale.preferred_scale(240);
+        });
+        harness.roundtrip();
+
+        // Both changes are applied with a single frame.
+        let surface = harness.surface();
+        assert_eq!(surface.frame_requests, 1);
+        assert_eq!(surface.current.buffer.unwrap().shm_size(), Some((200, 200)));
+    }
+
+    #[test]
+    fn missing_optional_globals() {
+        let image = TestImage::new("missing_optional_globals", 100, 200);
+        let globals =
+            Globals { fractional_scale: false, single_pixel_buffer: false, ..Globals::default() };
+        let mut harness = Harness::new(globals, &["--shm", "-i", image.path()]);
+        harness.configure(100, 100);
+
+        let surface = harness.surface();
+        assert!(surface.fractional_scale.is_none());
+        let buffer = surface.current.buffer.as_ref().unwrap();
+        assert_eq!(buffer.shm_size(), Some((100, 200)));
+
+        // Integer scale is used without fractional scaling support.
+        harness.with_server(|server| server.surface().resource.preferred_buffer_scale(2));
+        harness.roundtrip();
+
+        let surface = harness.surface();
+        assert_eq!(surface.current.viewport_source, Some((0., 100., 200., 200.)));
+        let buffer = surface.current.buffer.as_ref().unwrap();
+        assert_eq!(buffer.shm_size(), Some((200, 400)));
+    }
+
+    #[test]
+    fn image_reload() {
+        let image = TestImage::new("image_reload", 200, 100);
+        let mut harness = Harness::new(Globals::default(), &["--shm", "-i", image.path()]);
+        harness.configure(100, 100);
+
+        let buffer = harness.surface().current.buffer.unwrap();
+        assert_eq!(buffer.shm_size(), Some((200, 100)));
+
+        // Overwrite the image with a different aspect ratio.
+        let _image = TestImage::new("image_reload", 100, 200);
+        harness.state.wallpaper.reload_image();
+        harness.roundtrip();
+
+        let surface = harness.surface();
+        assert_eq!(surface.current.viewport_source, Some((0., 50., 100., 100.)));
+        let buffer = surface.current.buffer.as_ref().unwrap();
+        assert_eq!(buffer.shm_size(), Some((100, 200)));
+
+        // Broken images keep the current image.
+        std::fs::write(image.path(), b"corrupted").unwrap();
+        harness.state.wallpaper.reload_image();
+        harness.roundtrip();
+
+        let buffer = harness.surface().current.buffer.unwrap();
+        assert_eq!(buffer.shm_size(), Some((100, 200)));
+    }
+
+    #[test]
+    fn orientation_images() {
+        let portrait = TestImage::new("orientation_portrait", 100, 300);
+        let landscape = TestImage::new("orientation_landscape", 200, 100);
+        let args = [
+            "--shm",
+            "--portrait-image",
+            portrait.path(),
+            "--landscape-image",
+            landscape.path(),
+            "--portrait-focus",
+            "0+0",
+        ];
+        let mut harness = Harness::new(Globals::default(), &args);
+        harness.configure(100, 200);
+
+        let surface = harness.surface();
+        assert_eq!(surface.current.viewport_source, Some((0., 0., 100., 200.)));
+        assert_eq!(surface.current.buffer.unwrap().shm_size(), Some((100, 300)));
+
+        // Rotating the surface switches to the landscape image.
+        harness.configure(200, 100);
+
+        let surface = harness.surface();
+        assert_eq!(surface.current.viewport_source, Some((0., 0., 200., 100.)));
+        assert_eq!(surface.current.buffer.unwrap().shm_size(), Some((200, 100)));
+    }
+
+    #[test]
+    fn buffer_transform() {
+        let image = TestImage::new("buffer_transform", 200, 100);
+        let mut harness = Harness::new(Globals::default(), &["--shm", "-i", image.path()]);
+        harness.configure(200, 100);
+        harness.with_server(|server| {
+            server.surface().resource.preferred_buffer_transform(Transform::_90);
+        });
+        harness.roundtrip();
+
+        // The buffer is rotated, while the viewport stays in surface coordinates.
+        let surface = harness.surface();
+        assert_eq!(surface.current.buffer_transform, Some(Transform::_90));
+        assert_eq!(surface.current.viewport_source, Some((0., 0., 200., 100.)));
+        let buffer = surface.current.buffer.as_ref().unwrap();
+        assert_eq!(buffer.shm_size(), Some((100, 200)));
+        assert_eq!(buffer.shm_pixel(50, 10), Some([255, 0, 0, 255]));
+        assert_eq!(buffer.shm_pixel(50, 190), Some([0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn shm_image_dropped_after_upload() {
+        let image = TestImage::new("shm_image_dropped_after_upload", 200, 100);
+        let mut harness = Harness::new(Globals::default(), &["--shm", "-i", image.path()]);
+        harness.configure(200, 100);
+
+        // Replace the file without notifying the wallpaper.
+        RgbImage::from_pixel(200, 100, Rgb([0, 255, 0])).save(image.path()).unwrap();
+
+        // The image is loaded from its source again for the rotated buffer.
+        harness.with_server(|server| {
+            server.surface().resource.preferred_buffer_transform(Transform::_90);
+        });
+        harness.roundtrip();
+
+        // Wait for the redraw from the frame callback to be committed.
+        harness.roundtrip();
+
+        let buffer = harness.surface().current.buffer.unwrap();
+        assert_eq!(buffer.shm_size(), Some((100, 200)));
+        assert_eq!(buffer.shm_pixel(50, 10), Some([0, 255, 0, 255]));
+    }
+
+    #[test]
+    fn image_fd() {
+        let image = TestImage::new("image_fd", 200, 100);
+        let file = File::open(image.path()).unwrap();
+        let fd = file.as_raw_fd().to_string();
+        let mut harness = Harness::new(Globals::default(), &["--shm", "--image-fd", &fd]);
+        harness.configure(100, 100);
+
+        let buffer = harness.surface().current.buffer.unwrap();
+        assert_eq!(buffer.shm_size(), Some((200, 100)));
+        assert_eq!(buffer.shm_pixel(10, 50), Some([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn image_fd_reload() {
+        let image = TestImage::new("image_fd_reload", 200, 100);
+        let portrait = TestImage::new("image_fd_reload_portrait", 100, 300);
+        let file = File::open(image.path()).unwrap();
+        let fd = file.as_raw_fd().to_string();
+        let args = ["--shm", "--image-fd", &fd, "--portrait-image", portrait.path()];
+        let mut harness = Harness::new(Globals::default(), &args);
+        harness.configure(200, 100);
+        harness.configure(100, 200);
+        assert_eq!(harness.surface().current.buffer.unwrap().shm_size(), Some((100, 300)));
+
+        // Switching back decodes the image without reading the FD again.
+        harness.configure(200, 100);
+
+        let buffer = harness.surface().current.buffer.unwrap();
+        assert_eq!(buffer.shm_size(), Some((200, 100)));
+        assert_eq!(buffer.shm_pixel(10, 50), Some([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn overlay_composition() {
+        let image = TestImage::new("overlay_composition", 20, 10);
+        let overlay = format!("{},anchor=top-left,size=20,margin=10", image.path());
+        let mut harness =
+            Harness::new(Globals::default(), &["--shm", "-c", "#00FF00", "--overlay", &overlay]);
+        harness.configure(100, 100);
+        harness.with_server(|server| {
+            let surface = server.surface();
+            surface.fractional_scale.as_ref().unwrap().preferred_scale(240);
+        });
+        harness.roundtrip();
+
+        // All layers are composed into a single buffer at the physical size.
+        let surface = harness.surface();
+        assert_eq!(surface.current.viewport_source, Some((0., 0., 200., 200.)));
+        let buffer = surface.current.buffer.as_ref().unwrap();
+        assert_eq!(buffer.shm_size(), Some((200, 200)));
+        assert_eq!(buffer.shm_pixel(10, 10), Some([0, 255, 0, 255]));
+        assert_eq!(buffer.shm_pixel(25, 25), Some([255, 0, 0, 255]));
+        assert_eq!(buffer.shm_pixel(55, 25), Some([0, 0, 255, 255]));
+        assert_eq!(buffer.shm_pixel(65, 25), Some([0, 255, 0, 255]));
+    }
+
+    #[test]
+    fn text_partial_damage() {
+        // Skip without any system fonts.
+        if crate::text::default_font().is_none() {
+            return;
+        }
+
+        let args = ["--shm", "--text", "%S,anchor=top-left,margin=10,size=20"];
+        let mut harness = Harness::new(Globals::default(), &args);
+        harness.configure(200, 100);
+
+        let surface = harness.surface();
+        assert_eq!(surface.current.damage, vec![(0, 0, 200, 100)]);
+        let buffer = surface.current.buffer.as_ref().unwrap();
+        assert_eq!(buffer.shm_size(), Some((200, 100)));
+
+        // Only the text is damaged once its content changes.
+        thread::sleep(Duration::from_millis(1100));
+        harness.state.wallpaper.update();
+        harness.roundtrip();
+
+        let surface = harness.surface();
+        assert!(!surface.current.damage.is_empty());
+        for (x, y, width, height) in surface.current.damage {
+            assert!(x >= 10 && y >= 10);
+            assert!(x + width <= 60 && y + height <= 40);
+        }
+    }
+
+    #[test]
+    fn color_without_single_pixel_buffer() {
+        let globals = Globals { single_pixel_buffer: false, ..Globals::default() };
+        let mut harness = Harness::new(globals, &["-c", "#FF3300"]);
+        harness.configure(100, 100);
+
+        // Without a working EGL display, a shm color buffer must be used instead.
+        let surface = harness.surface();
+        let buffer = surface.current.buffer.as_ref().unwrap();
+        assert_eq!(buffer.shm_size(), Some((100, 100)));
+        assert_eq!(buffer.shm_pixel(50, 50).map(|[r, g, b, _]| [r, g, b]), Some([255, 51, 0]));
+    }
+
+    #[test]
+    fn shm_pattern() {
+        let args = ["--shm", "-c", "#000000", "--pattern", "checkerboard,color=ffffff,size=10"];
+        let mut harness = Harness::new(Globals::default(), &args);
+        harness.configure(100, 100);
+
+        // Patterns are rendered at the surface's size, without an image.
+        let surface = harness.surface();
+        let buffer = surface.current.buffer.as_ref().unwrap();
+        assert_eq!(buffer.shm_size(), Some((100, 100)));
+        assert_eq!(buffer.shm_pixel(5, 5).map(|[r, g, b, _]| [r, g, b]), Some([0, 0, 0]));
+        assert_eq!(buffer.shm_pixel(15, 5).map(|[r, g, b, _]| [r, g, b]), Some([255, 255, 255]));
+    }
+
+    #[test]
+    fn integer_scale_ignored_with_fractional_scale() {
+        let image = TestImage::new("integer_scale_ignored", 100, 100);
+        let mut harness = Harness::new(Globals::default(), &["--shm", "-i", image.path()]);
+        harness.configure(100, 100);
+
+        harness.with_server(|server| server.surface().resource.preferred_buffer_scale(2));
+        harness.roundtrip();
+
+        let buffer = harness.surface().current.buffer.unwrap();
+        assert_eq!(buffer.shm_size(), Some((100, 100)));
+    }
+
+    /// Temporary test image, split into a red and a blue half.
+    struct TestImage {
+        path: String,
+    }
+
+    impl TestImage {
+        fn new(name: &str, width: u32, height: u32) -> Self {
+            let image = RgbImage::from_fn(width, height, |x, _| match x < width / 2 {
+                true => Rgb([255, 0, 0]),
+                false => Rgb([0, 0, 255]),
+            });
+            Self::save(name, image.into())
+        }
+
+        /// Create an image with an opaque red left half and a transparent right
+        /// half.
+        fn translucent(name: &str, width: u32, height: u32) -> Self {
+            let image = RgbaImage::from_fn(width, height, |x, _| match x < width / 2 {
+                true => Rgba([255, 0, 0, 255]),
+                false => Rgba([0, 0, 255, 0]),
+            });
+            Self::save(name, image.into())
+        }
+
+        fn save(name: &str, image: DynamicImage) -> Self {
+            let file_name = format!("tabula-{}-{name}.png", std::process::id());
+            let path = std::env::temp_dir().join(file_name).to_string_lossy().into_owned();
+            image.save(&path).unwrap();
+
+            Self { path }
+        }
+
+        fn path(&self) -> &str {
+            &self.path
+        }
+    }
+
+    impl Drop for TestImage {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Globals advertised by the mock compositor.
+#[derive(Copy, Clone)]
+struct Globals {
+    single_pixel_buffer: bool,
+    fractional_scale: bool,
+    layer_shell: bool,
+    compositor_version: u32,
+    /// Scale of each advertised output.
+    output_scales: &'static [i32],
+}
+
+impl Default for Globals {
+    fn default() -> Self {
+        Self {
+            single_pixel_buffer: true,
+            fractional_scale: true,
+            layer_shell: true,
+            compositor_version: 6,
+            output_scales: &[],
+        }
+    }
+}
+
+/// Tabula client connected to a mock compositor.
+struct Harness {
+    server: Arc<Mutex<MockCompositor>>,
+    terminate: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+
+    queue: EventQueue<State>,
+    state: State,
+
+    serial: u32,
+}
+
+impl Harness {
+    /// Start the compositor and connect a Tabula client to it.
+    fn new(globals: Globals, args: &[&str]) -> Self {
+        match Self::try_new(globals, args) {
+            Ok(harness) => harness,
+            Err(err) => panic!("failed to create state: {err}"),
+        }
+    }
+
+    /// Start the compositor and try to connect a Tabula client to it.
+    fn try_new(globals: Globals, args: &[&str]) -> Result<Self, Error> {
+        let (server_socket, client_socket) = UnixStream::pair().unwrap();
+
+        // Start mock compositor.
+        let compositor = MockCompositor::new(globals);
+        compositor.display.handle().insert_client(server_socket, Arc::new(ClientState)).unwrap();
+        let server = Arc::new(Mutex::new(compositor));
+        let terminate = Arc::new(AtomicBool::new(false));
+        let thread = Some(MockCompositor::spawn(server.clone(), terminate.clone()));
+
+        // Connect client.
+        let connection = Connection::from_socket(client_socket).unwrap();
+        let (globals, queue) = globals::registry_queue_init(&connection)?;
+
+        let mut options = Options::parse_from(iter::once("tabula").chain(args.iter().copied()));
+
+        // Disable the image cache and state file, to avoid touching the user's
+        // directories.
+        options.cache_size = 0;
+        let state = State::new(&connection, &globals, &queue.handle(), options, false);
+
+        // Ensure the client state is stopped on error.
+        let mut harness = match state {
+            Ok(state) => {
+                Self { server, terminate, thread, queue, state, serial: Default::default() }
+            },
+            Err(err) => {
+                terminate.store(true, Ordering::Relaxed);
+                let _ = thread.map(|thread| thread.join());
+                return Err(err);
+            },
+        };
+
+        harness.roundtrip();
+
+        Ok(harness)
+    }
+
+    /// Send a layer shell configure and wait for the client to process it.
+    fn configure(&mut self, width: u32, height: u32) {
+        self.serial += 1;
+        let serial = self.serial;
+        self.with_server(|server| {
+            let layer_surface = server.surface().layer_surface.as_ref().unwrap();
+            layer_surface.configure(serial, width, height);
+        });
+        self.roundtrip();
+    }
+
+    /// Run a closure on the compositor and flush the events it sent.
+    fn with_server<F: FnOnce(&mut Server)>(&mut self, fun: F) {
+        self.with_display(|server, _| fun(server));
+    }
+
+    /// Run a closure on the compositor and its display, and flush the events it
+    /// sent.
+    fn with_display<F: FnOnce(&mut Server, &DisplayHandle)>(&mut self, fun: F) {
+        let mut compositor = self.server.lock().unwrap();
+        let MockCompositor { display, server } = &mut *compositor;
+        fun(server, &display.handle());
+        display.flush_clients().unwrap();
+    }
+
+    /// Wait for all pending requests and events to be processed.
+    fn roundtrip(&mut self) {
+        self.queue.roundtrip(&mut self.state).unwrap();
+        self.state.reopen_closed();
+
+        // Ensure requests sent in response to events are processed too.
+        self.queue.roundtrip(&mut self.state).unwrap();
+    }
+
+    /// Get the state of the client's layer shell surface.
+    fn surface(&mut self) -> Surface {
+        self.server.lock().unwrap().server.surface().clone()
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        self.terminate.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// In-process Wayland compositor.
+struct MockCompositor {
+    display: Display<Server>,
+    server: Server,
+}
+
+impl MockCompositor {
+    fn new(globals: Globals) -> Self {
+        let display = Display::new().unwrap();
+        let handle = display.handle();
+
+        handle.create_global::<Server, wl_compositor::WlCompositor, _>(
+            globals.compositor_version,
+            (),
+        );
+        handle.create_global::<Server, wl_shm::WlShm, _>(1, ());
+        handle.create_global::<Server, wp_viewporter::WpViewporter, _>(1, ());
+        if globals.layer_shell {
+            handle.create_global::<Server, ZwlrLayerShellV1, _>(4, ());
+        }
+        if globals.fractional_scale {
+            handle.create_global::<Server, WpFractionalScaleManagerV1, _>(1, ());
+        }
+        if globals.single_pixel_buffer {
+            handle.create_global::<Server, WpSinglePixelBufferManagerV1, _>(1, ());
+        }
+        let output_globals = globals
+            .output_scales
+            .iter()
+            .map(|scale| handle.create_global::<Server, wl_output::WlOutput, _>(4, *scale))
+            .collect();
+
+        Self { display, server: Server { output_globals, ..Default::default() } }
+    }
+
+    /// Dispatch client requests on a background thread.
+    fn spawn(compositor: Arc<Mutex<Self>>, terminate: Arc<AtomicBool>) -> JoinHandle<()> {
+        thread::spawn(move || {
+            while !terminate.load(Ordering::Relaxed) {
+                {
+                    let mut compositor = compositor.lock().unwrap();
+                    let MockCompositor { display, server } = &mut *compositor;
+                    display.dispatch_clients(server).unwrap();
+                    display.flush_clients().unwrap();
+                }
+
+                thread::sleep(Duration::from_millis(1));
+            }
+        })
+    }
+}
+
+struct ClientState;
+impl ClientData for ClientState {}
+
+/// Compositor state recorded from client requests.
+#[derive(Default)]
+struct Server {
+    surfaces: HashMap<ObjectId, Surface>,
+    /// Bound outputs, in the order of their globals.
+    outputs: Vec<wl_output::WlOutput>,
+    output_globals: Vec<GlobalId>,
+}
+
+impl Server {
+    /// Get the surface with the layer shell role.
+    fn surface(&mut self) -> &mut Surface {
+        let mut surfaces = self.surfaces.values_mut().filter(|s| s.layer_surface.is_some());
+        let surface = surfaces.next().expect("no layer surface");
+        assert!(surfaces.next().is_none(), "multiple layer surfaces");
+        surface
+    }
+
+    /// Get state for a surface resource.
+    fn surface_state(&mut self, surface: &wl_surface::WlSurface) -> &mut Surface {
+        self.surfaces.get_mut(&surface.id()).expect("unknown surface")
+    }
+}
+
+/// Surface state.
+#[derive(Clone, Debug)]
+struct Surface {
+    resource: wl_surface::WlSurface,
+    layer_surface: Option<ZwlrLayerSurfaceV1>,
+    fractional_scale: Option<WpFractionalScaleV1>,
+
+    pending: SurfaceState,
+    current: SurfaceState,
+
+    frame_callbacks: Vec<wl_callback::WlCallback>,
+    frame_requests: usize,
+
+    acked_serial: Option<u32>,
+    commits: usize,
+}
+
+impl Surface {
+    fn new(resource: wl_surface::WlSurface) -> Self {
+        Self {
+            resource,
+            fractional_scale: Default::default(),
+            layer_surface: Default::default(),
+            frame_callbacks: Default::default(),
+            frame_requests: Default::default(),
+            acked_serial: Default::default(),
+            commits: Default::default(),
+            pending: Default::default(),
+            current: Default::default(),
+        }
+    }
+
+    /// Apply pending surface state.
+    ///
+    /// Frame callbacks are completed immediately, as if the surface was
+    /// presented right after the commit.
+    fn commit(&mut self) {
+        self.current = self.pending.clone();
+        self.pending.damage.clear();
+        self.commits += 1;
+
+        for callback in self.frame_callbacks.drain(..) {
+            callback.done(0);
+        }
+    }
+}
+
+/// Double-buffered surface state.
+#[derive(Clone, Default, Debug)]
+struct SurfaceState {
+    viewport_source: Option<(f64, f64, f64, f64)>,
+    viewport_destination: Option<(i32, i32)>,
+    opaque_region: Option<Vec<Rect>>,
+    layer: Option<LayerState>,
+    buffer: Option<Buffer>,
+    buffer_transform: Option<Transform>,
+    damage: Vec<Rect>,
+}
+
+/// Double-buffered layer shell surface state.
+#[derive(Clone, Debug)]
+struct LayerState {
+    layer: Option<Layer>,
+    namespace: String,
+    anchor: Anchor,
+    exclusive_zone: i32,
+    size: (u32, u32),
+    margin: (i32, i32, i32, i32),
+}
+
+impl LayerState {
+    fn new(layer: Option<Layer>, namespace: String) -> Self {
+        Self {
+            namespace,
+            layer,
+            anchor: Anchor::empty(),
+            exclusive_zone: Default::default(),
+            margin: Default::default(),
+            size: Default::default(),
+        }
+    }
+}
+
+/// Rectangle as `(x, y, width, height)`.
+type Rect = (i32, i32, i32, i32);
+
+/// Attached buffer content.
+#[derive(Clone, Debug)]
+enum Buffer {
+    SinglePixel { r: u32, g: u32, b: u32, a: u32 },
+    Shm { file: Arc<File>, offset: i32, width: i32, height: i32, stride: i32 },
+}
+
+impl Buffer {
+    /// Size of a shared memory buffer.
+    fn shm_size(&self) -> Option<(i32, i32)> {
+        match self {
+            Self::Shm { width, height, .. } => Some((*width, *height)),
+            Self::SinglePixel { .. } => None,
+        }
+    }
+
+    /// Read an XRGB pixel from a shared memory buffer as RGBA.
+    #[cfg(feature = "png")]
+    fn shm_pixel(&self, x: i32, y: i32) -> Option<[u8; 4]> {
+        use std::os::unix::fs::FileExt;
+
+        match self {
+            Self::Shm { file, offset, stride, .. } => {
+                let mut pixel = [0; 4];
+                let position = *offset as u64 + (y * stride + x * 4) as u64;
+                file.read_exact_at(&mut pixel, position).unwrap();
+                let [b, g, r, a] = pixel;
+                Some([r, g, b, a])
+            },
+            Self::SinglePixel { .. } => None,
+        }
+    }
+}
+
+impl PartialEq for Buffer {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                Self::SinglePixel { r, g, b, a },
+                Self::SinglePixel { r: other_r, g: other_g, b: other_b, a: other_a },
+            ) => (r, g, b, a) == (other_r, other_g, other_b, other_a),
+            (
+                Self::Shm { file, offset, width, height, stride },
+                Self::Shm {
+                    file: other_file,
+                    offset: other_offset,
+                    width: other_width,
+                    height: other_height,
+                    stride: other_stride,
+                },
+            ) => {
+                Arc::ptr_eq(file, other_file)
+                    && (offset, width, height, stride)
+                        == (other_offset, other_width, other_height, other_stride)
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Bind a global without any state.
+macro_rules! stateless_global {
+    ($($interface:ty),*) => {
+        $(
+            impl GlobalDispatch<$interface, ()> for Server {
+                fn bind(
+                    _state: &mut Self,
+                    _handle: &DisplayHandle,
+                    _client: &Client,
+                    resource: New<$interface>,
+                    _global_data: &(),
+                    data_init: &mut DataInit<'_, Self>,
+                ) {
+                    data_init.init(resource, ());
+                }
+            }
+        )*
+    };
+}
+
+stateless_global!(
+    wl_compositor::WlCompositor,
+    wp_viewporter::WpViewporter,
+    ZwlrLayerShellV1,
+    WpFractionalScaleManagerV1,
+    WpSinglePixelBufferManagerV1
+);
+
+impl GlobalDispatch<wl_shm::WlShm, ()> for Server {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<wl_shm::WlShm>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let shm = data_init.init(resource, ());
+        shm.format(wl_shm::Format::Argb8888);
+        shm.format(wl_shm::Format::Xrgb8888);
+    }
+}
+
+impl GlobalDispatch<wl_output::WlOutput, i32> for Server {
+    fn bind(
+        state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<wl_output::WlOutput>,
+        scale: &i32,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let output = data_init.init(resource, ());
+        output.scale(*scale);
+        output.done();
+        state.outputs.push(output);
+    }
+}
+
+impl Dispatch<wl_output::WlOutput, ()> for Server {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _output: &wl_output::WlOutput,
+        _request: wl_output::Request,
+        _data: &(),
+        _handle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl Dispatch<wl_compositor::WlCompositor, ()> for Server {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _compositor: &wl_compositor::WlCompositor,
+        request: wl_compositor::Request,
+        _data: &(),
+        _handle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wl_compositor::Request::CreateSurface { id } => {
+                let surface = data_init.init(id, ());
+                state.surfaces.insert(surface.id(), Surface::new(surface));
+            },
+            wl_compositor::Request::CreateRegion { id } => {
+                data_init.init(id, Mutex::new(Vec::new()));
+            },
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<wl_surface::WlSurface, ()> for Server {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        surface: &wl_surface::WlSurface,
+        request: wl_surface::Request,
+        _data: &(),
+        _handle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        // Forget surface state on destruction.
+        if let wl_surface::Request::Destroy = request {
+            state.surfaces.remove(&surface.id());
+            return;
+        }
+
+        let surface = state.surface_state(surface);
+        match request {
+            wl_surface::Request::Attach { buffer, .. } => {
+                surface.pending.buffer = buffer.and_then(|buffer| buffer.data::<Buffer>().cloned());
+            },
+            wl_surface::Request::Damage { x, y, width, height } => {
+                surface.pending.damage.push((x, y, width, height));
+            },
+            wl_surface::Request::SetOpaqueRegion { region } => {
+                let region = region.as_ref().and_then(|region| region.data::<Region>());
+                surface.pending.opaque_region = region.map(|rects| rects.lock().unwrap().clone());
+            },
+            wl_surface::Request::Frame { callback } => {
+                surface.frame_callbacks.push(data_init.init(callback, ()));
+                surface.frame_requests += 1;
+            },
+            wl_surface::Request::SetBufferTransform { transform } => {
+                surface.pending.buffer_transform = transform.into_result().ok();
+            },
+            wl_surface::Request::Commit => surface.commit(),
+            _ => (),
+        }
+    }
+}
+
+type Region = Mutex<Vec<Rect>>;
+
+impl Dispatch<wl_region::WlRegion, Region> for Server {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _region: &wl_region::WlRegion,
+        request: wl_region::Request,
+        rects: &Region,
+        _handle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let wl_region::Request::Add { x, y, width, height } = request {
+            rects.lock().unwrap().push((x, y, width, height));
+        }
+    }
+}
+
+impl Dispatch<wl_callback::WlCallback, ()> for Server {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _callback: &wl_callback::WlCallback,
+        _request: wl_callback::Request,
+        _data: &(),
+        _handle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        // No requests.
+    }
+}
+
+impl Dispatch<wl_shm::WlShm, ()> for Server {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _shm: &wl_shm::WlShm,
+        request: wl_shm::Request,
+        _data: &(),
+        _handle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let wl_shm::Request::CreatePool { id, fd, .. } = request {
+            data_init.init(id, Arc::new(File::from(fd)));
+        }
+    }
+}
+
+impl Dispatch<wl_shm_pool::WlShmPool, Arc<File>> for Server {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _pool: &wl_shm_pool::WlShmPool,
+        request: wl_shm_pool::Request,
+        file: &Arc<File>,
+        _handle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let wl_shm_pool::Request::CreateBuffer { id, offset, width, height, stride, format } =
+            request
+        {
+            assert_eq!(format, WEnum::Value(wl_shm::Format::Xrgb8888));
+            let file = file.clone();
+            data_init.init(id, Buffer::Shm { file, offset, width, height, stride });
+        }
+    }
+}
+
+impl Dispatch<wl_buffer::WlBuffer, Buffer> for Server {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _buffer: &wl_buffer::WlBuffer,
+        _request: wl_buffer::Request,
+        _data: &Buffer,
+        _handle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        // Buffers are only ever destroyed.
+    }
+}
+
+impl Dispatch<WpSinglePixelBufferManagerV1, ()> for Server {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _manager: &WpSinglePixelBufferManagerV1,
+        request: wp_single_pixel_buffer_manager_v1::Request,
+        _data: &(),
+        _handle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let wp_single_pixel_buffer_manager_v1::Request::CreateU32RgbaBuffer { id, r, g, b, a } =
+            request
+        {
+            data_init.init(id, Buffer::SinglePixel { r, g, b, a });
+        }
+    }
+}
+
+impl Dispatch<wp_viewporter::WpViewporter, ()> for Server {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _viewporter: &wp_viewporter::WpViewporter,
+        request: wp_viewporter::Request,
+        _data: &(),
+        _handle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let wp_viewporter::Request::GetViewport { id, surface } = request {
+            data_init.init(id, surface);
+        }
+    }
+}
+
+impl Dispatch<wp_viewport::WpViewport, wl_surface::WlSurface> for Server {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _viewport: &wp_viewport::WpViewport,
+        request: wp_viewport::Request,
+        surface: &wl_surface::WlSurface,
+        _handle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        let surface = state.surface_state(surface);
+        match request {
+            wp_viewport::Request::SetSource { x, y, width, height } => {
+                surface.pending.viewport_source = Some((x, y, width, height));
+            },
+            wp_viewport::Request::SetDestination { width, height } => {
+                surface.pending.viewport_destination = Some((width, height));
+            },
+            _ => (),
+        }
+    }
+}
+
+impl Dispatch<WpFractionalScaleManagerV1, ()> for Server {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _manager: &WpFractionalScaleManagerV1,
+        request: wp_fractional_scale_manager_v1::Request,
+        _data: &(),
+        _handle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let wp_fractional_scale_manager_v1::Request::GetFractionalScale { id, surface } = request
+        {
+            let fractional_scale = data_init.init(id, ());
+            state.surface_state(&surface).fractional_scale = Some(fractional_scale);
+        }
+    }
+}
+
+impl Dispatch<WpFractionalScaleV1, ()> for Server {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _fractional_scale: &WpFractionalScaleV1,
+        _request: wp_fractional_scale_v1::Request,
+        _data: &(),
+        _handle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        // Only destructor.
+    }
+}
+
+impl Dispatch<ZwlrLayerShellV1, ()> for Server {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _layer_shell: &ZwlrLayerShellV1,
+        request: zwlr_layer_shell_v1::Request,
+        _data: &(),
+        _handle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let zwlr_layer_shell_v1::Request::GetLayerSurface {
+            id, surface, layer, namespace, ..
+        } = request
+        {
+            let layer_surface = data_init.init(id, surface.clone());
+            let surface = state.surface_state(&surface);
+            surface.layer_surface = Some(layer_surface);
+            surface.pending.layer = Some(LayerState::new(layer.into_result().ok(), namespace));
+        }
+    }
+}
+
+impl Dispatch<ZwlrLayerSurfaceV1, wl_surface::WlSurface> for Server {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _layer_surface: &ZwlrLayerSurfaceV1,
+        request: zwlr_layer_surface_v1::Request,
+        surface: &wl_surface::WlSurface,
+        _handle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        let surface = state.surface_state(surface);
+        let layer = surface.pending.layer.as_mut().unwrap();
+        match request {
+            zwlr_layer_surface_v1::Request::SetSize { width, height } => {
+                layer.size = (width, height)
+            },
+            zwlr_layer_surface_v1::Request::SetAnchor { anchor } => {
+                layer.anchor = anchor.into_result().unwrap();
+            },
+            zwlr_layer_surface_v1::Request::SetExclusiveZone { zone } => {
+                layer.exclusive_zone = zone;
+            },
+            zwlr_layer_surface_v1::Request::SetMargin { top, right, bottom, left } => {
+                layer.margin = (top, right, bottom, left);
+            },
+            zwlr_layer_surface_v1::Request::SetLayer { layer: new_layer } => {
+                layer.layer = new_layer.into_result().ok();
+            },
+            zwlr_layer_surface_v1::Request::AckConfigure { serial } => {
+                surface.acked_serial = Some(serial);
+            },
+            _ => (),
+        }
+    }
+}
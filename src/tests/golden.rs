@@ -0,0 +1,227 @@
+//! Golden image regression tests.
+//!
+//! Wallpapers are rendered on the CPU and compared against the reference
+//! images in `tests/golden`. Set `TABULA_BLESS=1` to update the references
+//! after an intentional rendering change.
+
+use std::env;
+use std::path::PathBuf;
+
+use clap::Parser;
+use image::{ImageFormat, Rgba, RgbaImage};
+
+use crate::cli::Options;
+use crate::geometry::Size;
+use crate::software::{self, Layers, SoftwareRenderer};
+use crate::window::UnloadedImage;
+use crate::{backdrop, palette};
+
+/// Maximum allowed per-channel difference from the reference.
+const TOLERANCE: u8 = 2;
+
+/// Source image dimensions, covering square, wide, tall and panorama ratios.
+const IMAGE_SIZES: [(u32, u32); 4] = [(64, 64), (96, 54), (54, 96), (120, 40)];
+
+/// Logical surface dimensions.
+const SURFACE_SIZES: [(u32, u32); 3] = [(36, 78), (78, 36), (50, 50)];
+
+/// Surface scale factors.
+const SCALES: [f64; 3] = [1., 1.75, 2.25];
+
+/// Focus points.
+const FOCUS_POINTS: [&str; 4] = ["0.5+0.5", "0+0", "1+1", "0.25+0.75"];
+
+#[test]
+fn color_only() {
+    let options = Options::parse_from(["tabula", "-c", "#336699"]);
+    assert_golden("color_only", Size::new(20, 10), 1.5, None, &options);
+}
+
+#[test]
+fn overlays() {
+    let image = test_image(Size::new(64, 64));
+    let options = Options::parse_from([
+        "tabula",
+        "--overlay",
+        "logo,anchor=bottom-right,size=12,margin=4",
+        "--overlay",
+        "watermark,size=50%,opacity=0.5",
+    ]);
+    assert_golden("overlays", Size::new(60, 40), 1.5, Some(&image), &options);
+}
+
+#[test]
+fn fit_contain_auto() {
+    let image = test_image(Size::new(96, 54));
+    let options = Options::parse_from(["tabula", "-c", "auto", "--fit", "contain"]);
+    assert_golden("fit_contain_auto", Size::new(50, 50), 1.5, Some(&image), &options);
+}
+
+#[test]
+fn fit_center_auto_edge() {
+    let image = test_image(Size::new(64, 64));
+    let options =
+        Options::parse_from(["tabula", "-c", "auto-edge", "--fit", "center", "-f", "0.25+0.5"]);
+    assert_golden("fit_center_auto_edge", Size::new(60, 40), 2., Some(&image), &options);
+}
+
+#[test]
+fn backdrop_blur() {
+    let image = test_image(Size::new(96, 54));
+    let options = Options::parse_from(["tabula", "--fit", "contain", "--backdrop", "blur"]);
+    assert_golden("backdrop_blur", Size::new(36, 78), 1.75, Some(&image), &options);
+}
+
+#[test]
+fn crop_zoom() {
+    // Crop away the border and the top-left cell, then zoom into the bottom-right.
+    let image = test_image(Size::new(64, 64));
+    let options =
+        Options::parse_from(["tabula", "--crop", "16,16,47,0.5", "--zoom", "1.5", "-f", "1+1"]);
+    assert_golden("crop_zoom", Size::new(60, 40), 1., Some(&image), &options);
+}
+
+#[test]
+fn patterns() {
+    for kind in ["checkerboard", "stripes", "dots", "noise"] {
+        let spec = format!("{kind},color=e0c060,size=6");
+        let options = Options::parse_from(["tabula", "-c", "#204060", "--pattern", &spec]);
+        assert_golden(&format!("pattern_{kind}"), Size::new(40, 30), 1.75, None, &options);
+    }
+}
+
+#[test]
+fn layout_matrix() {
+    let mut failures = Vec::new();
+
+    let mut index = 0;
+    for (image_width, image_height) in IMAGE_SIZES {
+        let image = test_image(Size::new(image_width, image_height));
+        for (width, height) in SURFACE_SIZES {
+            for scale in SCALES {
+                // Rotate through focus points to cover them for every layout.
+                let focus = FOCUS_POINTS[index % FOCUS_POINTS.len()];
+                index += 1;
+
+                let options = Options::parse_from(["tabula", "-c", "#FF00FF", "-f", focus]);
+                let name = format!(
+                    "image{image_width}x{image_height}_surface{width}x{height}_scale{scale}_\
+                     focus{focus}"
+                );
+                let size = Size::new(width, height);
+                if let Err(err) = check_golden(&name, size, scale, Some(&image), &options) {
+                    failures.push(err);
+                }
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "golden image mismatches:\n{}", failures.join("\n"));
+}
+
+/// Assert that a wallpaper matches its reference image.
+fn assert_golden(name: &str, size: Size, scale: f64, image: Option<&RgbaImage>, options: &Options) {
+    if let Err(err) = check_golden(name, size, scale, image, options) {
+        panic!("golden image mismatch: {err}");
+    }
+}
+
+/// Compare a rendered wallpaper against its reference image.
+fn check_golden(
+    name: &str,
+    size: Size,
+    scale: f64,
+    image: Option<&RgbaImage>,
+    options: &Options,
+) -> Result<(), String> {
+    let physical_size = size * scale;
+    let mut renderer = SoftwareRenderer::new(physical_size);
+    let overlays: Vec<_> = options.overlays.iter().map(|_| overlay_image()).collect();
+    let unloaded_image = image.cloned().map(|image| UnloadedImage::from(image).crop(options.crop));
+    let image = unloaded_image.as_ref().map(UnloadedImage::to_rgba);
+    let color = palette::background(options.color, unloaded_image.as_ref());
+    let backdrop = backdrop::create(options, unloaded_image.as_ref());
+    let layers = Layers {
+        image: image.as_ref(),
+        backdrop: backdrop.as_ref(),
+        overlays: &overlays,
+        color,
+        ..Layers::default()
+    };
+    software::render(&mut renderer, physical_size, scale, layers, options);
+    let rendered = renderer.into_image();
+
+    let golden_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let reference_path = golden_dir.join(format!("{name}.png"));
+
+    // Update the reference image.
+    if env::var_os("TABULA_BLESS").is_some() {
+        rendered.save_with_format(&reference_path, ImageFormat::Png).unwrap();
+        return Ok(());
+    }
+
+    let reference = match image::open(&reference_path) {
+        Ok(reference) => reference.into_rgba8(),
+        Err(err) => return Err(format!("{name}: missing reference image ({err})")),
+    };
+
+    let result = compare(&rendered, &reference);
+
+    // Store the rendered image for inspection.
+    if result.is_err() {
+        let failure_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/golden-failures");
+        let _ = std::fs::create_dir_all(&failure_dir);
+        let _ =
+            rendered.save_with_format(failure_dir.join(format!("{name}.png")), ImageFormat::Png);
+    }
+
+    result.map_err(|err| format!("{name}: {err}"))
+}
+
+/// Compare two images within the per-channel tolerance.
+fn compare(rendered: &RgbaImage, reference: &RgbaImage) -> Result<(), String> {
+    if rendered.dimensions() != reference.dimensions() {
+        return Err(format!(
+            "expected size {:?}, got {:?}",
+            reference.dimensions(),
+            rendered.dimensions()
+        ));
+    }
+
+    let mismatches = rendered
+        .pixels()
+        .zip(reference.pixels())
+        .filter(|(a, b)| a.0.iter().zip(b.0).any(|(a, b)| a.abs_diff(b) > TOLERANCE))
+        .count();
+
+    match mismatches {
+        0 => Ok(()),
+        count => Err(format!("{count} pixels exceed the tolerance")),
+    }
+}
+
+/// Create an overlay image, with a transparent left half.
+///
+/// The same image is used for all overlays, ignoring their paths.
+fn overlay_image() -> RgbaImage {
+    RgbaImage::from_fn(16, 8, |x, _| match x < 8 {
+        true => Rgba([255, 255, 0, 0]),
+        false => Rgba([0, 255, 255, 255]),
+    })
+}
+
+/// Create a source image with a distinct color for each cell of a 4x4 grid.
+///
+/// The cells make any shift in the visible region obvious, while a border
+/// reveals which edges have been cropped.
+fn test_image(size: Size) -> RgbaImage {
+    RgbaImage::from_fn(size.width, size.height, |x, y| {
+        if x == 0 || y == 0 || x + 1 == size.width || y + 1 == size.height {
+            return Rgba([255, 255, 255, 255]);
+        }
+
+        let column = x * 4 / size.width;
+        let row = y * 4 / size.height;
+        Rgba([(column * 80) as u8, (row * 80) as u8, ((column + row) * 30) as u8, 255])
+    })
+}
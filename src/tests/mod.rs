@@ -0,0 +1,5 @@
+//! Integration tests.
+
+mod compositor;
+#[cfg(feature = "png")]
+mod golden;
@@ -0,0 +1,209 @@
+//! Text rasterization.
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use ab_glyph::{Font, FontArc, PxScale, ScaleFont, point};
+use chrono::{DateTime, Local, Timelike};
+use image::{Rgba, RgbaImage};
+
+use crate::Error;
+use crate::cli::{Rgb, Text};
+
+/// Fonts used when no font is specified explicitly.
+const DEFAULT_FONTS: [&str; 6] = [
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/noto/NotoSans-Regular.ttf",
+    "/usr/share/fonts/truetype/noto/NotoSans-Regular.ttf",
+    "/usr/share/fonts/liberation/LiberationSans-Regular.ttf",
+];
+
+/// Text with its current content.
+pub struct TextLayer {
+    font: FontArc,
+    content: String,
+    dynamic: bool,
+}
+
+impl TextLayer {
+    pub fn new(text: &Text) -> Result<Self, Error> {
+        let font = match &text.font {
+            Some(path) => load_font(path)?,
+            None => default_font().ok_or(Error::MissingFont)?,
+        };
+
+        // Only texts with format specifiers change over time.
+        let dynamic = text.format.replace("%%", "").contains('%');
+
+        let content = expand(&text.format, &Local::now());
+
+        Ok(Self { font, content, dynamic })
+    }
+
+    /// Update the text's content for the current time.
+    ///
+    /// Returns `true` if the content has changed.
+    pub fn update(&mut self, text: &Text, now: &DateTime<Local>) -> bool {
+        if !self.dynamic {
+            return false;
+        }
+
+        let content = expand(&text.format, now);
+        let changed = content != self.content;
+        self.content = content;
+        changed
+    }
+
+    /// Check if the text's content depends on the time.
+    pub fn is_dynamic(&self) -> bool {
+        self.dynamic
+    }
+
+    /// Rasterize the text at a scale factor.
+    ///
+    /// The shadow is offset by one logical pixel to the bottom right.
+    pub fn rasterize(&self, text: &Text, scale: f64) -> RgbaImage {
+        let font = self.font.as_scaled(PxScale::from(text.size * scale as f32));
+        let shadow_offset = match text.shadow {
+            Some(_) => scale.round().max(1.) as f32,
+            None => 0.,
+        };
+
+        // Layout glyphs along the baseline.
+        let mut glyphs = Vec::new();
+        let mut caret = 0.;
+        let mut last_glyph = None;
+        for c in self.content.chars() {
+            let glyph_id = font.glyph_id(c);
+            if let Some(last_glyph) = last_glyph {
+                caret += font.kern(last_glyph, glyph_id);
+            }
+            glyphs
+                .push(glyph_id.with_scale_and_position(font.scale(), point(caret, font.ascent())));
+            caret += font.h_advance(glyph_id);
+            last_glyph = Some(glyph_id);
+        }
+
+        let width = (caret + shadow_offset).ceil().max(1.) as u32;
+        let height = (font.height() + shadow_offset).ceil().max(1.) as u32;
+        let mut image = RgbaImage::new(width, height);
+
+        // Draw the shadow below the text.
+        let passes = text.shadow.map(|shadow| (shadow, shadow_offset)).into_iter();
+        for (color, offset) in passes.chain([(text.color, 0.)]) {
+            for glyph in &glyphs {
+                let mut glyph = glyph.clone();
+                glyph.position.x += offset;
+                glyph.position.y += offset;
+
+                let outline = match self.font.outline_glyph(glyph) {
+                    Some(outline) => outline,
+                    None => continue,
+                };
+
+                let bounds = outline.px_bounds();
+                outline.draw(|x, y, coverage| {
+                    let x = bounds.min.x as i32 + x as i32;
+                    let y = bounds.min.y as i32 + y as i32;
+                    if x >= 0 && y >= 0 && (x as u32) < width && (y as u32) < height {
+                        let pixel = image.get_pixel_mut(x as u32, y as u32);
+                        *pixel = blend_over(*pixel, color, coverage);
+                    }
+                });
+            }
+        }
+
+        image
+    }
+}
+
+/// Get the instant at which the next minute starts.
+pub fn next_minute(now: &DateTime<Local>) -> Instant {
+    let elapsed = Duration::new(now.second() as u64, now.nanosecond() % 1_000_000_000);
+    Instant::now() + Duration::from_secs(60).saturating_sub(elapsed)
+}
+
+/// Expand the time and hostname placeholders of a text format.
+fn expand(format: &str, now: &DateTime<Local>) -> String {
+    let format = if format.contains("{hostname}") {
+        // Escape percent signs, to avoid interpreting them as format specifiers.
+        let hostname = hostname().replace('%', "%%");
+        format.replace("{hostname}", &hostname)
+    } else {
+        format.into()
+    };
+
+    now.format(&format).to_string()
+}
+
+/// Get the system's hostname.
+fn hostname() -> String {
+    rustix::system::uname().nodename().to_string_lossy().into_owned()
+}
+
+/// Load a font from a file.
+fn load_font(path: &Path) -> Result<FontArc, Error> {
+    let bytes = fs::read(path)?;
+    Ok(FontArc::try_from_vec(bytes)?)
+}
+
+/// Load the first available default font.
+pub fn default_font() -> Option<FontArc> {
+    DEFAULT_FONTS.iter().find_map(|path| load_font(Path::new(path)).ok())
+}
+
+/// Blend a color with partial coverage over a pixel.
+fn blend_over(dst: Rgba<u8>, color: Rgb, coverage: f32) -> Rgba<u8> {
+    let alpha = coverage.clamp(0., 1.);
+    let dst_alpha = dst[3] as f32 / 255.;
+    let out_alpha = alpha + dst_alpha * (1. - alpha);
+    if out_alpha <= 0. {
+        return dst;
+    }
+
+    let src = [color.r, color.g, color.b];
+    let [r, g, b] = std::array::from_fn(|i| {
+        let value = src[i] as f32 * alpha + dst[i] as f32 * dst_alpha * (1. - alpha);
+        (value / out_alpha).round() as u8
+    });
+    Rgba([r, g, b, (out_alpha * 255.).round() as u8])
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn expand_format() {
+        let now = Local.with_ymd_and_hms(2024, 3, 9, 7, 5, 0).unwrap();
+        assert_eq!(expand("%H:%M", &now), "07:05");
+        assert_eq!(expand("100%% static", &now), "100% static");
+        assert_eq!(expand("{hostname}", &now), hostname());
+    }
+
+    #[test]
+    fn rasterize_shadow() {
+        let font = match default_font() {
+            Some(font) => font,
+            // Skip without any system fonts.
+            None => return,
+        };
+
+        let mut text = Text::new("Tabula");
+        let layer = TextLayer { font, content: text.format.clone(), dynamic: false };
+        let plain = layer.rasterize(&text, 2.);
+
+        text.shadow = Some(Rgb { r: 0, g: 0, b: 0 });
+        let shadowed = layer.rasterize(&text, 2.);
+
+        assert_eq!(shadowed.width(), plain.width() + 2);
+        assert_eq!(shadowed.height(), plain.height() + 2);
+        assert!(plain.pixels().any(|pixel| pixel.0 == [255, 255, 255, 255]));
+        assert!(shadowed.pixels().any(|pixel| pixel.0 == [0, 0, 0, 255]));
+    }
+}
@@ -0,0 +1,170 @@
+//! Image layout animations.
+
+use std::time::{Duration, Instant};
+
+use crate::geometry::{Position, Size};
+use crate::power::Throttle;
+
+/// Animated layout of a window's background image.
+///
+/// Layout changes are animated when the surface is resized, at a reduced
+/// frame rate when running on battery.
+pub struct LayoutAnimation {
+    transition: Option<Transition>,
+    /// Minimum time between animation frames.
+    frame_interval: Duration,
+    last_frame: Instant,
+}
+
+impl Default for LayoutAnimation {
+    fn default() -> Self {
+        Self { last_frame: Instant::now(), transition: None, frame_interval: Duration::ZERO }
+    }
+}
+
+impl LayoutAnimation {
+    /// Start animating away from the `current` layout.
+    ///
+    /// Running animations continue from their current position. No animation
+    /// is started for a zero `duration`, or when animations are frozen.
+    pub fn start(
+        &mut self,
+        current: (Position<f32>, Size<f32>),
+        duration: Duration,
+        throttle: Throttle,
+    ) {
+        if duration.is_zero() || throttle == Throttle::Frozen {
+            self.transition = None;
+            return;
+        }
+        self.frame_interval = throttle.frame_interval();
+
+        let now = Instant::now();
+        let from = self.layout(current, now);
+        self.transition = Some(Transition::new(from, now, duration));
+    }
+
+    /// Stop the running animation, jumping to the target layout.
+    pub fn stop(&mut self) {
+        self.transition = None;
+    }
+
+    /// Check if an animation is running.
+    pub fn is_running(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    /// Get the layout at a point in time, moving towards the target layout
+    /// `to`.
+    pub fn layout(
+        &self,
+        to: (Position<f32>, Size<f32>),
+        now: Instant,
+    ) -> (Position<f32>, Size<f32>) {
+        match &self.transition {
+            Some(transition) => transition.layout(to, now),
+            None => to,
+        }
+    }
+
+    /// Record that a frame is drawn.
+    ///
+    /// Finished animations are stopped, after drawing their final layout.
+    pub fn frame(&mut self, now: Instant) {
+        self.last_frame = now;
+        if self.transition.as_ref().is_some_and(|transition| transition.is_done(now)) {
+            self.transition = None;
+        }
+    }
+
+    /// Check if the next animation frame must be delayed, to reduce the frame
+    /// rate.
+    pub fn is_throttled(&self, now: Instant) -> bool {
+        self.is_running() && now.saturating_duration_since(self.last_frame) < self.frame_interval
+    }
+}
+
+/// Animation of the image's physical position and size after a resize.
+struct Transition {
+    from: (Position<f32>, Size<f32>),
+    start: Instant,
+    duration: Duration,
+}
+
+impl Transition {
+    /// Start animating away from a layout.
+    fn new(from: (Position<f32>, Size<f32>), start: Instant, duration: Duration) -> Self {
+        Self { from, start, duration }
+    }
+
+    /// Get the layout at a point in time, moving towards the target layout
+    /// `to`.
+    fn layout(&self, to: (Position<f32>, Size<f32>), now: Instant) -> (Position<f32>, Size<f32>) {
+        let elapsed = now.saturating_duration_since(self.start);
+        let progress = (elapsed.as_secs_f32() / self.duration.as_secs_f32()).min(1.);
+
+        // Ease out, to settle softly into the new layout.
+        let t = 1. - (1. - progress).powi(3);
+        let mix = |start: f32, end: f32| start + (end - start) * t;
+
+        let ((from_position, from_size), (to_position, to_size)) = (self.from, to);
+        let position =
+            Position::new(mix(from_position.x, to_position.x), mix(from_position.y, to_position.y));
+        let size =
+            Size::new(mix(from_size.width, to_size.width), mix(from_size.height, to_size.height));
+        (position, size)
+    }
+
+    /// Check if the animation has finished.
+    fn is_done(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.start) >= self.duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eased_layout() {
+        let start = Instant::now();
+        let from = (Position::new(0., 0.), Size::new(100., 200.));
+        let to = (Position::new(-50., 20.), Size::new(200., 100.));
+        let transition = Transition::new(from, start, Duration::from_millis(200));
+
+        assert_eq!(transition.layout(to, start), from);
+        assert!(!transition.is_done(start));
+
+        // Halfway through, the ease-out curve is already at 87.5%.
+        let (position, size) = transition.layout(to, start + Duration::from_millis(100));
+        assert_eq!(position, Position::new(-43.75, 17.5));
+        assert_eq!(size, Size::new(187.5, 112.5));
+
+        let end = start + Duration::from_millis(250);
+        assert_eq!(transition.layout(to, end), to);
+        assert!(transition.is_done(end));
+    }
+
+    #[test]
+    fn throttled_animation() {
+        let layout = (Position::new(0., 0.), Size::new(100., 200.));
+        let mut animation = LayoutAnimation::default();
+
+        // Frozen animations jump to their target.
+        animation.start(layout, Duration::from_millis(200), Throttle::Frozen);
+        assert!(!animation.is_running());
+
+        // Frames are delayed at a reduced rate.
+        animation.start(layout, Duration::from_millis(200), Throttle::Reduced);
+        let now = Instant::now();
+        animation.frame(now);
+        assert!(animation.is_running());
+        assert!(animation.is_throttled(now + Duration::from_millis(10)));
+        assert!(!animation.is_throttled(now + Duration::from_millis(40)));
+
+        // Finished animations are stopped.
+        animation.frame(now + Duration::from_millis(250));
+        assert!(!animation.is_running());
+        assert!(!animation.is_throttled(now + Duration::from_millis(260)));
+    }
+}
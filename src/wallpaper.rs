@@ -0,0 +1,362 @@
+//! Embeddable wallpaper surface.
+
+use std::mem;
+use std::os::fd::RawFd;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use smithay_client_toolkit::reexports::client::globals::GlobalList;
+use smithay_client_toolkit::reexports::client::protocol::wl_output::Transform;
+use smithay_client_toolkit::reexports::client::protocol::wl_surface::WlSurface;
+use smithay_client_toolkit::reexports::client::{Connection, QueueHandle};
+use smithay_client_toolkit::shell::WaylandSurface;
+use smithay_client_toolkit::shell::wlr_layer::{LayerSurface, LayerSurfaceConfigure};
+
+use crate::Error;
+use crate::cli::{
+    Anchors, Backdrop, Color, Crop, Fit, Layer, Margin, Options, Overlay, Pattern, Text,
+};
+use crate::geometry::{Orientation, Position, Size};
+use crate::wayland::{ProtocolStates, WallpaperState};
+use crate::window::Window;
+
+/// Wallpaper layer shell surface.
+///
+/// The wallpaper is driven by the event queue of its state `D`, which must
+/// forward the relevant events to the wallpaper's handler methods. See
+/// [`WallpaperState`] for the state's requirements.
+pub struct Wallpaper<D> {
+    pub(crate) protocol_states: ProtocolStates,
+    window: Window<D>,
+    closed: bool,
+}
+
+impl Wallpaper<()> {
+    /// Create a new wallpaper builder.
+    pub fn builder() -> WallpaperBuilder {
+        WallpaperBuilder::default()
+    }
+}
+
+impl<D: WallpaperState> Wallpaper<D> {
+    /// Get the wallpaper's layer shell surface.
+    pub fn layer_surface(&self) -> &LayerSurface {
+        self.window.layer_surface()
+    }
+
+    /// Handle layer shell configure events.
+    ///
+    /// Events for other layer surfaces are ignored.
+    pub fn configure(&mut self, layer: &LayerSurface, configure: LayerSurfaceConfigure) {
+        if layer == self.window.layer_surface() {
+            self.window.set_size(&self.protocol_states.compositor, configure.new_size.into());
+        }
+    }
+
+    /// Handle the compositor closing the layer shell surface.
+    ///
+    /// Nothing is drawn until the surface is recreated with [`Self::reopen`].
+    /// Events for other layer surfaces are ignored.
+    pub fn closed(&mut self, layer: &LayerSurface) {
+        if layer == self.window.layer_surface() {
+            self.closed = true;
+            self.window.close();
+        }
+    }
+
+    /// Check if the layer shell surface was closed by the compositor.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Recreate the layer shell surface after it was closed.
+    ///
+    /// Images scaled for the surface are loaded from the cache, so they are
+    /// usually not decoded again. This does nothing if the surface is still
+    /// open.
+    ///
+    /// This should only be called after all pending events were dispatched,
+    /// since compositors usually remove the closed surface's output right
+    /// after closing it.
+    pub fn reopen(&mut self) {
+        if mem::take(&mut self.closed) {
+            self.window.reopen(&self.protocol_states);
+        }
+    }
+
+    /// Handle integer surface scale changes.
+    ///
+    /// SCTK reports the compositor's preferred buffer scale, or the highest
+    /// scale of all outputs the surface is on, so surfaces straddling outputs
+    /// are never drawn blurry.
+    ///
+    /// This is ignored if the compositor supports fractional scaling. Events
+    /// for other surfaces are ignored.
+    pub fn scale_factor_changed(&mut self, surface: &WlSurface, factor: i32) {
+        if self.protocol_states.fractional_scale.is_none() && self.owns_surface(surface) {
+            self.window.set_scale_factor(factor as f64);
+        }
+    }
+
+    /// Handle fractional surface scale changes.
+    ///
+    /// Events for other surfaces are ignored.
+    pub fn fractional_scale_changed(&mut self, surface: &WlSurface, factor: f64) {
+        if self.owns_surface(surface) {
+            self.window.set_scale_factor(factor);
+        }
+    }
+
+    /// Handle changes to the surface's preferred buffer transform.
+    ///
+    /// Events for other surfaces are ignored.
+    pub fn transform_changed(&mut self, surface: &WlSurface, transform: Transform) {
+        if self.owns_surface(surface) {
+            self.window.set_transform(transform);
+        }
+    }
+
+    /// Handle frame callbacks.
+    ///
+    /// Events for other surfaces are ignored.
+    pub fn frame(&mut self, surface: &WlSurface) {
+        if self.owns_surface(surface) {
+            self.window.frame();
+        }
+    }
+
+    /// Reload the background image from disk.
+    ///
+    /// This should be called whenever the image file was modified. If the new
+    /// image cannot be loaded, the current image stays in place.
+    pub fn reload_image(&mut self) {
+        self.window.reload_image();
+    }
+
+    /// Update time-dependent content, like clocks, and apply images scaled in
+    /// the background.
+    ///
+    /// This should be called once [`Self::next_update`] is reached.
+    pub fn update(&mut self) {
+        self.window.update();
+    }
+
+    /// Get the time at which [`Self::update`] should be called next.
+    ///
+    /// This is `None` if the wallpaper has no time-dependent content and no
+    /// image is being scaled in the background.
+    pub fn next_update(&self) -> Option<Instant> {
+        self.window.next_update()
+    }
+
+    /// Check if a surface belongs to this wallpaper.
+    fn owns_surface(&self, surface: &WlSurface) -> bool {
+        self.window.layer_surface().wl_surface() == surface
+    }
+}
+
+/// Builder for [`Wallpaper`].
+#[derive(Default)]
+pub struct WallpaperBuilder {
+    options: Options,
+    save_state: bool,
+}
+
+impl WallpaperBuilder {
+    /// Replace all configuration options with the parsed CLI arguments.
+    ///
+    /// Options which only affect the standalone application, like
+    /// subcommands, are ignored.
+    pub(crate) fn options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Persist the displayed image, color and focus for `--restore`.
+    pub(crate) fn save_state(mut self, save_state: bool) -> Self {
+        self.save_state = save_state;
+        self
+    }
+
+    /// Set the background color.
+    pub fn color(mut self, color: impl Into<Color>) -> Self {
+        self.options.color = color.into();
+        self
+    }
+
+    /// Set the scaling of the background image.
+    pub fn fit(mut self, fit: Fit) -> Self {
+        self.options.fit = fit;
+        self
+    }
+
+    /// Set the region of the background image to show.
+    pub fn crop(mut self, crop: Crop) -> Self {
+        self.options.crop = Some(crop);
+        self
+    }
+
+    /// Set the factor by which the background image is enlarged past its fit.
+    ///
+    /// Factors below one are treated as one.
+    pub fn zoom(mut self, zoom: f32) -> Self {
+        self.options.zoom = zoom.max(1.);
+        self
+    }
+
+    /// Set the fill of the area around images which do not cover the surface.
+    pub fn backdrop(mut self, backdrop: Backdrop) -> Self {
+        self.options.backdrop = backdrop;
+        self
+    }
+
+    /// Set a procedural pattern drawn over the background color.
+    pub fn pattern(mut self, pattern: Pattern) -> Self {
+        self.options.pattern = Some(pattern);
+        self
+    }
+
+    /// Set the background image.
+    pub fn image(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.image = Some(path.into());
+        self.options.image_fd = None;
+        self
+    }
+
+    /// Add an image on top of the background.
+    pub fn overlay(mut self, overlay: Overlay) -> Self {
+        self.options.overlays.push(overlay);
+        self
+    }
+
+    /// Add text on top of the background and overlays.
+    pub fn text(mut self, text: Text) -> Self {
+        self.options.texts.push(text);
+        self
+    }
+
+    /// Read the background image from a file descriptor.
+    ///
+    /// The file descriptor is duplicated and must stay open until the
+    /// wallpaper is built.
+    pub fn image_fd(mut self, fd: RawFd) -> Self {
+        self.options.image = None;
+        self.options.image_fd = Some(fd);
+        self
+    }
+
+    /// Set the image's relative focus point.
+    pub fn focus(mut self, focus: Position<f32>) -> Self {
+        self.options.focus = focus;
+        self
+    }
+
+    /// Set the image used for an orientation, replacing the default image.
+    pub fn oriented_image(mut self, orientation: Orientation, path: impl Into<PathBuf>) -> Self {
+        match orientation {
+            Orientation::Portrait => self.options.portrait_image = Some(path.into()),
+            Orientation::Landscape => self.options.landscape_image = Some(path.into()),
+        }
+        self
+    }
+
+    /// Set the focus point used for an orientation, replacing the default.
+    pub fn oriented_focus(mut self, orientation: Orientation, focus: Position<f32>) -> Self {
+        match orientation {
+            Orientation::Portrait => self.options.portrait_focus = Some(focus),
+            Orientation::Landscape => self.options.landscape_focus = Some(focus),
+        }
+        self
+    }
+
+    /// Set the duration of the image's animation after a resize.
+    ///
+    /// A zero duration disables the animation.
+    pub fn transition_duration(mut self, duration: Duration) -> Self {
+        self.options.transition_duration = duration.as_millis() as u64;
+        self
+    }
+
+    /// Render images without OpenGL, using a shared memory buffer.
+    pub fn shm(mut self, shm: bool) -> Self {
+        self.options.shm = shm;
+        self
+    }
+
+    /// Set the layer shell layer of the surface.
+    pub fn layer(mut self, layer: Layer) -> Self {
+        self.options.layer = layer;
+        self
+    }
+
+    /// Set the layer shell namespace, used to identify the surface.
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.options.namespace = namespace.into();
+        self
+    }
+
+    /// Set the screen edges the surface is attached to.
+    pub fn anchor(mut self, anchor: Anchors) -> Self {
+        self.options.anchor = anchor;
+        self
+    }
+
+    /// Set the logical surface size.
+    ///
+    /// A zero width or height stretches the surface between its anchored
+    /// edges.
+    pub fn surface_size(mut self, size: Size) -> Self {
+        self.options.surface_size = size;
+        self
+    }
+
+    /// Set the distance to the anchored edges in logical pixels.
+    pub fn margin(mut self, margin: Margin) -> Self {
+        self.options.margin = margin;
+        self
+    }
+
+    /// Set the size of the area reserved for the surface at its anchored edge.
+    pub fn exclusive_zone(mut self, exclusive_zone: i32) -> Self {
+        self.options.exclusive_zone = exclusive_zone;
+        self
+    }
+
+    /// Set the battery charge in percent below which animations are frozen.
+    ///
+    /// Building the wallpaper fails for values above 100.
+    pub fn battery_threshold(mut self, percent: u8) -> Self {
+        self.options.battery_threshold = percent;
+        self
+    }
+
+    /// Set the size limit of the scaled image cache in MiB.
+    ///
+    /// A zero size disables the cache.
+    pub fn cache_size(mut self, size: u64) -> Self {
+        self.options.cache_size = size;
+        self
+    }
+
+    /// Write the image's color palette to a directory.
+    pub fn palette_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.palette_dir = Some(path.into());
+        self
+    }
+
+    /// Create the wallpaper's layer shell surface.
+    ///
+    /// This fails if the surface's layer shell options contradict each other.
+    pub fn build<D: WallpaperState>(
+        self,
+        connection: &Connection,
+        globals: &GlobalList,
+        queue: &QueueHandle<D>,
+    ) -> Result<Wallpaper<D>, Error> {
+        self.options.validate().map_err(Error::InvalidOptions)?;
+        let protocol_states = ProtocolStates::new(globals, queue)?;
+        let window =
+            Window::new(&protocol_states, connection, queue, self.options, self.save_state)?;
+        Ok(Wallpaper { protocol_states, window, closed: Default::default() })
+    }
+}
@@ -0,0 +1,159 @@
+//! Image file change monitoring.
+
+use std::ffi::OsString;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use rustix::fs::inotify::{self, CreateFlags, Reader, WatchFlags};
+use rustix::io::Errno;
+
+/// Time without further modifications before a change is reported.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Inotify watcher for files.
+///
+/// The files' parent directories are watched instead of the files themselves,
+/// so atomic replacement through `rename` is picked up just like in-place
+/// writes. Changes to any of the files are reported together.
+pub struct FileWatcher {
+    inotify: OwnedFd,
+    /// Watch descriptors of the parent directories, with their file's name.
+    files: Vec<(i32, OsString)>,
+    deadline: Option<Instant>,
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path) -> io::Result<Self> {
+        let inotify = inotify::init(CreateFlags::CLOEXEC | CreateFlags::NONBLOCK)?;
+        let mut watcher = Self { inotify, files: Default::default(), deadline: Default::default() };
+        watcher.watch(path)?;
+        Ok(watcher)
+    }
+
+    /// Add another file to the watcher.
+    pub fn watch(&mut self, path: &Path) -> io::Result<()> {
+        let file_name = path.file_name().ok_or(io::ErrorKind::InvalidInput)?.to_owned();
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+
+        let flags = WatchFlags::CLOSE_WRITE | WatchFlags::MOVED_TO | WatchFlags::CREATE;
+        let wd = inotify::add_watch(&self.inotify, directory, flags)?;
+        self.files.push((wd, file_name));
+
+        Ok(())
+    }
+
+    /// Process all pending inotify events.
+    pub fn read_events(&mut self) -> io::Result<()> {
+        let mut buffer = [MaybeUninit::uninit(); 4096];
+        let mut reader = Reader::new(&self.inotify, &mut buffer);
+
+        loop {
+            let event = match reader.next() {
+                Ok(event) => event,
+                Err(Errno::AGAIN) => return Ok(()),
+                Err(Errno::INTR) => continue,
+                Err(err) => return Err(err.into()),
+            };
+
+            // Restart the debounce timer on every change to our files.
+            let file_name = event.file_name().map(|name| name.to_bytes());
+            let watched = self.files.iter().any(|(wd, watched_name)| {
+                *wd == event.wd() && file_name == Some(watched_name.as_bytes())
+            });
+            if watched {
+                self.deadline = Some(Instant::now() + DEBOUNCE);
+            }
+        }
+    }
+
+    /// Time until the next debounced change is due.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Check if the file has settled after a change.
+    ///
+    /// Every change is only reported once.
+    pub fn take_change(&mut self) -> bool {
+        match self.deadline {
+            Some(deadline) if deadline <= Instant::now() => {
+                self.deadline = None;
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+impl AsFd for FileWatcher {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.inotify.as_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread};
+
+    use super::*;
+
+    #[test]
+    fn atomic_rename() {
+        let directory = std::env::temp_dir().join(format!("tabula-watch-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("wallpaper.png");
+        fs::write(&path, b"old").unwrap();
+
+        let mut watcher = FileWatcher::new(&path).unwrap();
+
+        // Unrelated files in the same directory are ignored.
+        fs::write(directory.join("other.png"), b"other").unwrap();
+        watcher.read_events().unwrap();
+        assert_eq!(watcher.timeout(), None);
+
+        // Replace the file through a rename.
+        let tmp_path = directory.join("wallpaper.png.tmp");
+        fs::write(&tmp_path, b"new").unwrap();
+        fs::rename(&tmp_path, &path).unwrap();
+        watcher.read_events().unwrap();
+        assert!(watcher.timeout().is_some());
+        assert!(!watcher.take_change());
+
+        thread::sleep(DEBOUNCE);
+        assert!(watcher.take_change());
+        assert!(!watcher.take_change());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn multiple_files() {
+        let directory =
+            std::env::temp_dir().join(format!("tabula-watch-multi-{}", std::process::id()));
+        let portrait_directory = directory.join("portrait");
+        fs::create_dir_all(&portrait_directory).unwrap();
+        let landscape_path = directory.join("landscape.png");
+        let portrait_path = portrait_directory.join("portrait.png");
+
+        let mut watcher = FileWatcher::new(&landscape_path).unwrap();
+        watcher.watch(&portrait_path).unwrap();
+
+        // Files are only matched within their own directory.
+        fs::write(portrait_directory.join("landscape.png"), b"other").unwrap();
+        watcher.read_events().unwrap();
+        assert_eq!(watcher.timeout(), None);
+
+        fs::write(&portrait_path, b"new").unwrap();
+        watcher.read_events().unwrap();
+        assert!(watcher.timeout().is_some());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
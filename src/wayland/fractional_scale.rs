@@ -5,13 +5,9 @@ use _fs::wp_fractional_scale_v1::{Event as FractionalScalingEvent, WpFractionalS
 use smithay_client_toolkit::globals::GlobalData;
 use smithay_client_toolkit::reexports::client::globals::{BindError, GlobalList};
 use smithay_client_toolkit::reexports::client::protocol::wl_surface::WlSurface;
-use smithay_client_toolkit::reexports::client::{
-    Connection, Dispatch, Proxy, QueueHandle, delegate_dispatch,
-};
+use smithay_client_toolkit::reexports::client::{Connection, Dispatch, Proxy, QueueHandle};
 use smithay_client_toolkit::reexports::protocols::wp::fractional_scale::v1::client as _fs;
 
-use crate::State;
-
 /// The scaling factor denominator.
 const SCALE_DENOMINATOR: f64 = 120.;
 
@@ -35,29 +31,38 @@ pub struct FractionalScaleManager {
 
 impl FractionalScaleManager {
     /// Create new viewporter.
-    pub fn new(globals: &GlobalList, queue_handle: &QueueHandle<State>) -> Result<Self, BindError> {
+    pub fn new<D>(globals: &GlobalList, queue_handle: &QueueHandle<D>) -> Result<Self, BindError>
+    where
+        D: Dispatch<WpFractionalScaleManagerV1, GlobalData> + 'static,
+    {
         let manager = globals.bind(queue_handle, 1..=1, GlobalData)?;
         Ok(Self { manager })
     }
 
-    pub fn fractional_scaling(
+    pub fn fractional_scaling<D>(
         &self,
-        queue_handle: &QueueHandle<State>,
+        queue_handle: &QueueHandle<D>,
         surface: &WlSurface,
-    ) -> WpFractionalScaleV1 {
+    ) -> WpFractionalScaleV1
+    where
+        D: Dispatch<WpFractionalScaleV1, FractionalScale> + 'static,
+    {
         let data = FractionalScale { surface: surface.clone() };
         self.manager.get_fractional_scale(surface, queue_handle, data)
     }
 }
 
-impl Dispatch<WpFractionalScaleManagerV1, GlobalData, State> for FractionalScaleManager {
+impl<D> Dispatch<WpFractionalScaleManagerV1, GlobalData, D> for FractionalScaleManager
+where
+    D: Dispatch<WpFractionalScaleManagerV1, GlobalData>,
+{
     fn event(
-        _: &mut State,
+        _: &mut D,
         _: &WpFractionalScaleManagerV1,
         _: <WpFractionalScaleManagerV1 as Proxy>::Event,
         _: &GlobalData,
         _: &Connection,
-        _: &QueueHandle<State>,
+        _: &QueueHandle<D>,
     ) {
         // No events.
     }
@@ -67,14 +72,17 @@ pub struct FractionalScale {
     surface: WlSurface,
 }
 
-impl Dispatch<WpFractionalScaleV1, FractionalScale, State> for FractionalScale {
+impl<D> Dispatch<WpFractionalScaleV1, FractionalScale, D> for FractionalScale
+where
+    D: Dispatch<WpFractionalScaleV1, FractionalScale> + FractionalScaleHandler,
+{
     fn event(
-        state: &mut State,
+        state: &mut D,
         _: &WpFractionalScaleV1,
         event: <WpFractionalScaleV1 as Proxy>::Event,
         data: &FractionalScale,
         connection: &Connection,
-        queue: &QueueHandle<State>,
+        queue: &QueueHandle<D>,
     ) {
         if let FractionalScalingEvent::PreferredScale { scale } = event {
             let fractional_scale = scale as f64 / SCALE_DENOMINATOR;
@@ -82,6 +90,3 @@ impl Dispatch<WpFractionalScaleV1, FractionalScale, State> for FractionalScale {
         }
     }
 }
-
-delegate_dispatch!(State: [WpFractionalScaleManagerV1: GlobalData] => FractionalScaleManager);
-delegate_dispatch!(State: [WpFractionalScaleV1: FractionalScale] => FractionalScale);
@@ -1,29 +1,115 @@
 //! Wayland protocol handling.
 
 use _spb::wp_single_pixel_buffer_manager_v1::{self, WpSinglePixelBufferManagerV1};
-use smithay_client_toolkit::compositor::{CompositorHandler, CompositorState};
+use smithay_client_toolkit::compositor::{CompositorHandler, CompositorState, SurfaceData};
+use smithay_client_toolkit::globals::GlobalData;
 use smithay_client_toolkit::output::{OutputHandler, OutputState};
 use smithay_client_toolkit::reexports::client::globals::GlobalList;
 use smithay_client_toolkit::reexports::client::protocol::wl_buffer::{self, WlBuffer};
+use smithay_client_toolkit::reexports::client::protocol::wl_callback::WlCallback;
+use smithay_client_toolkit::reexports::client::protocol::wl_compositor::WlCompositor;
 use smithay_client_toolkit::reexports::client::protocol::wl_output::{Transform, WlOutput};
+use smithay_client_toolkit::reexports::client::protocol::wl_shm::WlShm;
 use smithay_client_toolkit::reexports::client::protocol::wl_surface::WlSurface;
 use smithay_client_toolkit::reexports::client::{Connection, Dispatch, QueueHandle};
+use smithay_client_toolkit::reexports::protocols::wp::fractional_scale::v1::client as _fs;
 use smithay_client_toolkit::reexports::protocols::wp::single_pixel_buffer::v1::client as _spb;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client as _vp;
+use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client as _ls;
 use smithay_client_toolkit::registry::{ProvidesRegistryState, RegistryState};
 use smithay_client_toolkit::shell::wlr_layer::{
-    LayerShell, LayerShellHandler, LayerSurface, LayerSurfaceConfigure,
+    LayerShell, LayerShellHandler, LayerSurface, LayerSurfaceConfigure, LayerSurfaceData,
 };
+use smithay_client_toolkit::shm::{Shm, ShmHandler};
 use smithay_client_toolkit::{
-    delegate_compositor, delegate_layer, delegate_output, delegate_registry, registry_handlers,
+    delegate_compositor, delegate_layer, delegate_output, delegate_registry, delegate_shm,
+    registry_handlers,
 };
 
-use crate::wayland::fractional_scale::{FractionalScaleHandler, FractionalScaleManager};
+use crate::wayland::fractional_scale::{
+    FractionalScale, FractionalScaleHandler, FractionalScaleManager,
+};
 use crate::wayland::viewporter::Viewporter;
 use crate::{Error, State};
 
 pub mod fractional_scale;
 pub mod viewporter;
 
+/// Event queue state requirements for driving a [`Wallpaper`].
+///
+/// This is implemented automatically for every state which dispatches all
+/// objects created by the wallpaper. Besides SCTK's compositor, layer shell
+/// and shm delegates, this requires [`delegate_wallpaper`] and
+/// [`FractionalScaleHandler`] for the state.
+///
+/// [`Wallpaper`]: crate::Wallpaper
+/// [`delegate_wallpaper`]: crate::delegate_wallpaper
+pub trait WallpaperState:
+    Dispatch<WlCompositor, GlobalData>
+    + Dispatch<WlSurface, SurfaceData>
+    + Dispatch<WlCallback, WlSurface>
+    + Dispatch<WlShm, GlobalData>
+    + Dispatch<WlBuffer, ()>
+    + Dispatch<_ls::zwlr_layer_shell_v1::ZwlrLayerShellV1, GlobalData>
+    + Dispatch<_ls::zwlr_layer_surface_v1::ZwlrLayerSurfaceV1, LayerSurfaceData>
+    + Dispatch<_vp::wp_viewporter::WpViewporter, GlobalData>
+    + Dispatch<_vp::wp_viewport::WpViewport, GlobalData>
+    + Dispatch<_fs::wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1, GlobalData>
+    + Dispatch<_fs::wp_fractional_scale_v1::WpFractionalScaleV1, FractionalScale>
+    + Dispatch<WpSinglePixelBufferManagerV1, ()>
+    + LayerShellHandler
+    + ShmHandler
+    + Sized
+    + 'static
+{
+}
+
+impl<D> WallpaperState for D where
+    D: Dispatch<WlCompositor, GlobalData>
+        + Dispatch<WlSurface, SurfaceData>
+        + Dispatch<WlCallback, WlSurface>
+        + Dispatch<WlShm, GlobalData>
+        + Dispatch<WlBuffer, ()>
+        + Dispatch<_ls::zwlr_layer_shell_v1::ZwlrLayerShellV1, GlobalData>
+        + Dispatch<_ls::zwlr_layer_surface_v1::ZwlrLayerSurfaceV1, LayerSurfaceData>
+        + Dispatch<_vp::wp_viewporter::WpViewporter, GlobalData>
+        + Dispatch<_vp::wp_viewport::WpViewport, GlobalData>
+        + Dispatch<_fs::wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1, GlobalData>
+        + Dispatch<_fs::wp_fractional_scale_v1::WpFractionalScaleV1, FractionalScale>
+        + Dispatch<WpSinglePixelBufferManagerV1, ()>
+        + LayerShellHandler
+        + ShmHandler
+        + 'static
+{
+}
+
+/// Delegate dispatch of the wallpaper's Wayland objects.
+///
+/// This covers all protocols not handled by SCTK's own delegates.
+#[macro_export]
+macro_rules! delegate_wallpaper {
+    ($ty:ty) => {
+        $crate::reexports::client::delegate_dispatch!($ty: [
+            $crate::reexports::protocols::wp::fractional_scale::v1::client::wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1: $crate::reexports::GlobalData
+        ] => $crate::wayland::fractional_scale::FractionalScaleManager);
+        $crate::reexports::client::delegate_dispatch!($ty: [
+            $crate::reexports::protocols::wp::fractional_scale::v1::client::wp_fractional_scale_v1::WpFractionalScaleV1: $crate::wayland::fractional_scale::FractionalScale
+        ] => $crate::wayland::fractional_scale::FractionalScale);
+        $crate::reexports::client::delegate_dispatch!($ty: [
+            $crate::reexports::protocols::wp::viewporter::client::wp_viewporter::WpViewporter: $crate::reexports::GlobalData
+        ] => $crate::wayland::viewporter::Viewporter);
+        $crate::reexports::client::delegate_dispatch!($ty: [
+            $crate::reexports::protocols::wp::viewporter::client::wp_viewport::WpViewport: $crate::reexports::GlobalData
+        ] => $crate::wayland::viewporter::Viewporter);
+        $crate::reexports::client::delegate_dispatch!($ty: [
+            $crate::reexports::protocols::wp::single_pixel_buffer::v1::client::wp_single_pixel_buffer_manager_v1::WpSinglePixelBufferManagerV1: ()
+        ] => $crate::wayland::ProtocolStates);
+        $crate::reexports::client::delegate_dispatch!($ty: [
+            $crate::reexports::client::protocol::wl_buffer::WlBuffer: ()
+        ] => $crate::wayland::ProtocolStates);
+    };
+}
+
 /// Wayland protocol globals.
 #[derive(Debug)]
 pub struct ProtocolStates {
@@ -31,17 +117,16 @@ pub struct ProtocolStates {
     pub fractional_scale: Option<FractionalScaleManager>,
     pub compositor: CompositorState,
     pub layer_shell: LayerShell,
-    pub registry: RegistryState,
     pub viewporter: Viewporter,
-
-    output: OutputState,
+    pub shm: Shm,
 }
 
 impl ProtocolStates {
-    pub fn new(globals: &GlobalList, queue: &QueueHandle<State>) -> Result<Self, Error> {
+    pub fn new<D: WallpaperState>(
+        globals: &GlobalList,
+        queue: &QueueHandle<D>,
+    ) -> Result<Self, Error> {
         let single_pixel_buffer = globals.bind(queue, 1..=1, ()).ok();
-        let registry = RegistryState::new(globals);
-        let output = OutputState::new(globals, queue);
         let layer_shell = LayerShell::bind(globals, queue)
             .map_err(|err| Error::WaylandProtocol("wlr_layer_shell", err))?;
         let compositor = CompositorState::bind(globals, queue)
@@ -49,16 +134,45 @@ impl ProtocolStates {
         let viewporter = Viewporter::new(globals, queue)
             .map_err(|err| Error::WaylandProtocol("wp_viewporter", err))?;
         let fractional_scale = FractionalScaleManager::new(globals, queue).ok();
+        let shm = Shm::bind(globals, queue).map_err(|err| Error::WaylandProtocol("wl_shm", err))?;
+
+        Ok(Self { single_pixel_buffer, fractional_scale, layer_shell, compositor, viewporter, shm })
+    }
+}
+
+impl<D> Dispatch<WpSinglePixelBufferManagerV1, (), D> for ProtocolStates
+where
+    D: Dispatch<WpSinglePixelBufferManagerV1, ()>,
+{
+    fn event(
+        _state: &mut D,
+        _manager: &WpSinglePixelBufferManagerV1,
+        _event: wp_single_pixel_buffer_manager_v1::Event,
+        _data: &(),
+        _connection: &Connection,
+        _queue: &QueueHandle<D>,
+    ) {
+        // No events.
+    }
+}
 
-        Ok(Self {
-            single_pixel_buffer,
-            fractional_scale,
-            layer_shell,
-            compositor,
-            viewporter,
-            registry,
-            output,
-        })
+impl<D> Dispatch<WlBuffer, (), D> for ProtocolStates
+where
+    D: Dispatch<WlBuffer, ()>,
+{
+    fn event(
+        _state: &mut D,
+        _buffer: &WlBuffer,
+        event: wl_buffer::Event,
+        _data: &(),
+        _connection: &Connection,
+        _queue: &QueueHandle<D>,
+    ) {
+        match event {
+            // We never release our SPB and SHM buffers.
+            wl_buffer::Event::Release => (),
+            event => unreachable!("Buffer event: {event:?}"),
+        }
     }
 }
 
@@ -67,56 +181,59 @@ impl CompositorHandler for State {
         &mut self,
         _connection: &Connection,
         _queue: &QueueHandle<Self>,
-        _surface: &WlSurface,
+        surface: &WlSurface,
         factor: i32,
     ) {
-        if self.protocol_states.fractional_scale.is_none() {
-            self.window.set_scale_factor(factor as f64);
-        }
+        self.wallpaper.scale_factor_changed(surface, factor);
     }
 
     fn frame(
         &mut self,
         _connection: &Connection,
         _queue: &QueueHandle<Self>,
-        _surface: &WlSurface,
+        surface: &WlSurface,
         _time: u32,
     ) {
-        self.window.draw();
+        self.wallpaper.frame(surface);
     }
 
     fn transform_changed(
         &mut self,
-        _: &Connection,
-        _: &QueueHandle<Self>,
-        _: &WlSurface,
-        _: Transform,
+        _connection: &Connection,
+        _queue: &QueueHandle<Self>,
+        surface: &WlSurface,
+        transform: Transform,
     ) {
+        self.wallpaper.transform_changed(surface, transform);
     }
 
     fn surface_enter(
         &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
+        _connection: &Connection,
+        _queue: &QueueHandle<Self>,
         _surface: &WlSurface,
         _output: &WlOutput,
     ) {
+        // Scale changes from entered outputs are reported through
+        // `scale_factor_changed`.
     }
 
     fn surface_leave(
         &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
+        _connection: &Connection,
+        _queue: &QueueHandle<Self>,
         _surface: &WlSurface,
         _output: &WlOutput,
     ) {
+        // Scale changes from left outputs are reported through
+        // `scale_factor_changed`.
     }
 }
 delegate_compositor!(State);
 
 impl OutputHandler for State {
     fn output_state(&mut self) -> &mut OutputState {
-        &mut self.protocol_states.output
+        &mut self.output
     }
 
     fn new_output(
@@ -125,6 +242,7 @@ impl OutputHandler for State {
         _queue: &QueueHandle<Self>,
         _output: WlOutput,
     ) {
+        self.wallpaper.reopen();
     }
 
     fn update_output(
@@ -146,19 +264,25 @@ impl OutputHandler for State {
 delegate_output!(State);
 
 impl LayerShellHandler for State {
-    fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _layer: &LayerSurface) {
-        self.terminated = true;
+    fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, layer: &LayerSurface) {
+        if !self.reopen {
+            self.terminated = true;
+            return;
+        }
+
+        // Reopen once pending output removals are dispatched, or a new output is added.
+        self.wallpaper.closed(layer);
     }
 
     fn configure(
         &mut self,
         _conn: &Connection,
         _queue: &QueueHandle<Self>,
-        _layer: &LayerSurface,
+        layer: &LayerSurface,
         configure: LayerSurfaceConfigure,
         _serial: u32,
     ) {
-        self.window.set_size(&self.protocol_states.compositor, configure.new_size.into());
+        self.wallpaper.configure(layer, configure);
     }
 }
 delegate_layer!(State);
@@ -168,48 +292,27 @@ impl FractionalScaleHandler for State {
         &mut self,
         _connection: &Connection,
         _queue: &QueueHandle<Self>,
-        _surface: &WlSurface,
+        surface: &WlSurface,
         factor: f64,
     ) {
-        self.window.set_scale_factor(factor);
+        self.wallpaper.fractional_scale_changed(surface, factor);
+    }
+}
+
+impl ShmHandler for State {
+    fn shm_state(&mut self) -> &mut Shm {
+        &mut self.wallpaper.protocol_states.shm
     }
 }
+delegate_shm!(State);
 
 impl ProvidesRegistryState for State {
     registry_handlers![OutputState];
 
     fn registry(&mut self) -> &mut RegistryState {
-        &mut self.protocol_states.registry
+        &mut self.registry
     }
 }
 delegate_registry!(State);
 
-impl Dispatch<WpSinglePixelBufferManagerV1, ()> for State {
-    fn event(
-        _state: &mut State,
-        _manager: &WpSinglePixelBufferManagerV1,
-        _event: wp_single_pixel_buffer_manager_v1::Event,
-        _data: &(),
-        _connection: &Connection,
-        _queue: &QueueHandle<State>,
-    ) {
-        // No events.
-    }
-}
-
-impl Dispatch<WlBuffer, ()> for State {
-    fn event(
-        _state: &mut State,
-        _buffer: &WlBuffer,
-        event: wl_buffer::Event,
-        _data: &(),
-        _connection: &Connection,
-        _queue: &QueueHandle<State>,
-    ) {
-        match event {
-            // We never release our SPB buffers.
-            wl_buffer::Event::Release => (),
-            event => unreachable!("SPB event: {event:?}"),
-        }
-    }
-}
+crate::delegate_wallpaper!(State);
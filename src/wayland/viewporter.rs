@@ -5,13 +5,9 @@ use _vp::wp_viewporter::WpViewporter;
 use smithay_client_toolkit::globals::GlobalData;
 use smithay_client_toolkit::reexports::client::globals::{BindError, GlobalList};
 use smithay_client_toolkit::reexports::client::protocol::wl_surface::WlSurface;
-use smithay_client_toolkit::reexports::client::{
-    Connection, Dispatch, Proxy, QueueHandle, delegate_dispatch,
-};
+use smithay_client_toolkit::reexports::client::{Connection, Dispatch, Proxy, QueueHandle};
 use smithay_client_toolkit::reexports::protocols::wp::viewporter::client as _vp;
 
-use crate::State;
-
 /// Viewporter.
 #[derive(Debug)]
 pub struct Viewporter {
@@ -20,41 +16,50 @@ pub struct Viewporter {
 
 impl Viewporter {
     /// Create new viewporter.
-    pub fn new(globals: &GlobalList, queue_handle: &QueueHandle<State>) -> Result<Self, BindError> {
+    pub fn new<D>(globals: &GlobalList, queue_handle: &QueueHandle<D>) -> Result<Self, BindError>
+    where
+        D: Dispatch<WpViewporter, GlobalData> + 'static,
+    {
         let viewporter = globals.bind(queue_handle, 1..=1, GlobalData)?;
         Ok(Self { viewporter })
     }
 
     /// Get the viewport for the given object.
-    pub fn viewport(&self, queue_handle: &QueueHandle<State>, surface: &WlSurface) -> WpViewport {
+    pub fn viewport<D>(&self, queue_handle: &QueueHandle<D>, surface: &WlSurface) -> WpViewport
+    where
+        D: Dispatch<WpViewport, GlobalData> + 'static,
+    {
         self.viewporter.get_viewport(surface, queue_handle, GlobalData)
     }
 }
 
-impl Dispatch<WpViewporter, GlobalData, State> for Viewporter {
+impl<D> Dispatch<WpViewporter, GlobalData, D> for Viewporter
+where
+    D: Dispatch<WpViewporter, GlobalData>,
+{
     fn event(
-        _: &mut State,
+        _: &mut D,
         _: &WpViewporter,
         _: <WpViewporter as Proxy>::Event,
         _: &GlobalData,
         _: &Connection,
-        _: &QueueHandle<State>,
+        _: &QueueHandle<D>,
     ) {
         // No events.
     }
 }
-impl Dispatch<WpViewport, GlobalData, State> for Viewporter {
+impl<D> Dispatch<WpViewport, GlobalData, D> for Viewporter
+where
+    D: Dispatch<WpViewport, GlobalData>,
+{
     fn event(
-        _: &mut State,
+        _: &mut D,
         _: &WpViewport,
         _: <WpViewport as Proxy>::Event,
         _: &GlobalData,
         _: &Connection,
-        _: &QueueHandle<State>,
+        _: &QueueHandle<D>,
     ) {
         // No events.
     }
 }
-
-delegate_dispatch!(State: [WpViewporter: GlobalData] => Viewporter);
-delegate_dispatch!(State: [WpViewport: GlobalData] => Viewporter);
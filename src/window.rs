@@ -1,175 +1,642 @@
 //! Wayland window rendering.
 
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+use std::mem;
+use std::ops::Deref;
+use std::os::fd::BorrowedFd;
 use std::path::Path;
 use std::ptr::NonNull;
+use std::time::{Duration, Instant};
 
+use chrono::{DateTime, Local, Timelike};
 use glutin::display::{Display, DisplayApiPreference};
-use image::{ColorType, ImageReader};
+use image::imageops::{self, FilterType};
+use image::{
+    ColorType, DynamicImage, ImageBuffer, ImageReader, Luma, LumaA, Pixel, Rgb, Rgba, RgbaImage,
+};
+use memmap2::Mmap;
 use raw_window_handle::{RawDisplayHandle, WaylandDisplayHandle};
 use smithay_client_toolkit::compositor::{CompositorState, Region};
 use smithay_client_toolkit::reexports::client::protocol::wl_buffer::WlBuffer;
+use smithay_client_toolkit::reexports::client::protocol::wl_output::Transform;
+use smithay_client_toolkit::reexports::client::protocol::wl_shm::WlShm;
 use smithay_client_toolkit::reexports::client::{Connection, QueueHandle};
-use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewport::WpViewport;
-use smithay_client_toolkit::shell::WaylandSurface;
-use smithay_client_toolkit::shell::wlr_layer::{Anchor, Layer, LayerSurface};
+use smithay_client_toolkit::shell::wlr_layer::LayerSurface;
+use tracing::{error, warn};
 
-use crate::cli::Options;
-use crate::geometry::{Position, Size};
-use crate::renderer::{Renderer, Texture};
-use crate::wayland::ProtocolStates;
-use crate::{Error, State, gl};
+use crate::cache::{self, ImageCache, ScaleJob};
+use crate::cli::{self, Crop, Fit, Options, PatternKind};
+use crate::geometry::{Orientation, Position, Size};
+use crate::power::{PowerSupply, Throttle};
+use crate::renderer::{Renderer, RendererError, Texture};
+use crate::shm::ShmImage;
+use crate::state::StateFile;
+use crate::surface::Surface;
+use crate::text::{self, TextLayer};
+use crate::transition::LayoutAnimation;
+use crate::wayland::{ProtocolStates, WallpaperState};
+use crate::{Error, backdrop, gl, palette, pattern};
+
+/// Minutes between sky gradient redraws.
+const SKY_INTERVAL: u32 = 10;
+
+/// Minutes between sky gradient redraws on battery.
+const SKY_INTERVAL_BATTERY: u32 = 30;
+
+/// Number of times the OpenGL context is recreated before giving up.
+const GL_RETRIES: usize = 2;
 
 /// Wayland window.
-pub struct Window {
-    surface: LayerSurface,
-    viewport: WpViewport,
-    renderer: Renderer,
+pub struct Window<D> {
+    queue: QueueHandle<D>,
+    surface: Surface,
+    renderer: Option<Renderer>,
 
+    /// Options for the current orientation.
     options: Options,
+    /// Options independent of the orientation.
+    base_options: Options,
+    orientation: Option<Orientation>,
+    /// Encoded image read from stdin or `--image-fd`.
+    stream: Option<Vec<u8>>,
 
     spb_buffer: Option<WlBuffer>,
+    shm_image: Option<ShmImage>,
     image: Option<Image>,
+    backdrop: Option<Image>,
+    overlays: Vec<Image>,
+    wl_shm: WlShm,
+    cache: Option<ImageCache>,
+    cached_size: Option<Size>,
+    scale_job: Option<ScaleJob>,
+    color: cli::Rgb,
+    state_file: Option<StateFile>,
+
+    texts: Vec<TextLayer>,
+    text_images: Vec<Image>,
+    text_sizes: Vec<Size>,
+    text_rects: Vec<(Position, Size)>,
+    next_update: Option<Instant>,
+    power: PowerSupply,
+    sky_outdated: bool,
+    /// Time of the last full redraw of the sky pattern.
+    sky_time: DateTime<Local>,
+    animation: LayoutAnimation,
 
     size: Size,
     scale: f64,
+    transform: Transform,
+
+    frame_pending: bool,
+    texts_outdated: bool,
+    transform_outdated: bool,
+    full_damage: bool,
+    dirty: bool,
 }
 
-impl Window {
+impl<D: WallpaperState> Window<D> {
     pub fn new(
         protocol_states: &ProtocolStates,
         connection: &Connection,
-        queue: &QueueHandle<State>,
-        options: Options,
+        queue: &QueueHandle<D>,
+        mut options: Options,
+        save_state: bool,
     ) -> Result<Self, Error> {
-        // Get EGL display.
-        let display = NonNull::new(connection.backend().display_ptr().cast()).unwrap();
-        let wayland_display = WaylandDisplayHandle::new(display);
-        let raw_display = RawDisplayHandle::Wayland(wayland_display);
-        let egl_display = unsafe { Display::new(raw_display, DisplayApiPreference::Egl)? };
-
-        // Create surface's Wayland global handles.
-        let surface = protocol_states.compositor.create_surface(queue);
-        if let Some(fractional_scale) = &protocol_states.fractional_scale {
-            fractional_scale.fractional_scaling(queue, &surface);
-        }
-        let viewport = protocol_states.viewporter.viewport(queue, &surface);
-
-        // Create the layer shell window.
-        let surface = protocol_states.layer_shell.create_layer_surface(
-            queue,
-            surface,
-            Layer::Background,
-            Some("wallpaper"),
-            None,
-        );
-        surface.set_anchor(Anchor::LEFT | Anchor::TOP | Anchor::RIGHT | Anchor::BOTTOM);
-        surface.set_exclusive_zone(-1);
-        surface.set_size(0, 0);
-        surface.commit();
-
-        // Create OpenGL renderer.
-        let wl_surface = surface.wl_surface();
-        let renderer = Renderer::new(egl_display, wl_surface.clone());
-
-        // Try to load the background image.
-        let image = match &options.image {
-            Some(image_path) => Some(UnloadedImage::new(image_path)?.into()),
-            None => None,
+        let surface = Surface::new(protocol_states, queue, &options);
+
+        // Read streamed images once, since they cannot be read again.
+        let stream = UnloadedImage::read_stream(&options)?;
+
+        // Defer loading orientation-specific images until the surface is configured.
+        let base_options = options.clone();
+        let deferred = options.has_oriented_images();
+        if deferred {
+            options.image = None;
+            options.image_fd = None;
+        }
+
+        // Try to load the background image, preferring a previously scaled copy.
+        let cache = ImageCache::new(options.cache_size * 1024 * 1024);
+        let cached = options
+            .image_path()
+            .zip(cache.as_ref())
+            .and_then(|(path, cache)| cache.load_latest(path, options.scaling()));
+        let (image, cached_size) = match cached {
+            Some((image, physical_size)) => (Some(image), Some(physical_size)),
+            None => (UnloadedImage::from_options(&options, stream.as_deref())?, None),
+        };
+        if let Some(image) = &image {
+            palette::update(&options, image);
+        }
+        let color = palette::background(options.color, image.as_ref());
+        let backdrop = backdrop::create(&options, image.as_ref());
+        let overlays = options
+            .overlays
+            .iter()
+            .map(|overlay| UnloadedImage::new(&overlay.path))
+            .collect::<Result<Vec<_>, _>>()?;
+        let texts = options.texts.iter().map(TextLayer::new).collect::<Result<Vec<_>, _>>()?;
+
+        // Schedule updates for clocks and the sky.
+        let sky_time = Local::now();
+        let is_sky = options.pattern.is_some_and(|pattern| pattern.kind == PatternKind::Sky);
+        let next_update = (is_sky || texts.iter().any(TextLayer::is_dynamic))
+            .then(|| text::next_minute(&sky_time));
+
+        // Upload image into a shared memory buffer, if GPU-free rendering is requested.
+        let wl_shm = protocol_states.shm.wl_shm().clone();
+        let has_image = image.is_some()
+            || deferred
+            || !overlays.is_empty()
+            || !texts.is_empty()
+            || options.pattern.is_some();
+        let (image, backdrop, overlays, shm_image) = if options.shm && has_image {
+            let overlays = overlays.iter().map(UnloadedImage::to_rgba).collect();
+            let mut shm_image = ShmImage::new(wl_shm.clone(), image, overlays, color, sky_time);
+            shm_image.set_backdrop(backdrop);
+            (None, None, Vec::new(), Some(shm_image))
+        } else {
+            let backdrop = backdrop.map(|backdrop| UnloadedImage::from(backdrop).into());
+            let overlays = overlays.into_iter().map(Image::from).collect();
+            (image.map(Image::from), backdrop, overlays, None)
         };
 
         // If no image is used and SPB is supported, use it to draw the background.
         let spb_buffer =
-            protocol_states.single_pixel_buffer.as_ref().filter(|_| options.image.is_none()).map(
-                |spb| {
-                    let [r, g, b] = [
-                        options.color.r as u32 * (u32::MAX / 255),
-                        options.color.g as u32 * (u32::MAX / 255),
-                        options.color.b as u32 * (u32::MAX / 255),
-                    ];
-                    spb.create_u32_rgba_buffer(r, g, b, u32::MAX, queue, ())
+            protocol_states.single_pixel_buffer.as_ref().filter(|_| !has_image).map(|spb| {
+                let [r, g, b] = [
+                    color.r as u32 * (u32::MAX / 255),
+                    color.g as u32 * (u32::MAX / 255),
+                    color.b as u32 * (u32::MAX / 255),
+                ];
+                spb.create_u32_rgba_buffer(r, g, b, u32::MAX, queue, ())
+            });
+
+        // Create OpenGL renderer, unless the surface can be drawn without it.
+        let renderer = if spb_buffer.is_some() || shm_image.is_some() {
+            None
+        } else if !wayland_sys::egl::is_lib_available() {
+            warn!("libwayland-egl unavailable, falling back to software rendering");
+            None
+        } else {
+            // Get EGL display.
+            let display = NonNull::new(connection.backend().display_ptr().cast()).unwrap();
+            let wayland_display = WaylandDisplayHandle::new(display);
+            let raw_display = RawDisplayHandle::Wayland(wayland_display);
+            match unsafe { Display::new(raw_display, DisplayApiPreference::Egl) } {
+                Ok(egl_display) => Some(Renderer::new(egl_display, surface.wl_surface().clone())),
+                Err(err) => {
+                    warn!("EGL unavailable, falling back to software rendering: {err}");
+                    None
                 },
-            );
+            }
+        };
 
-        Ok(Self {
+        let power = PowerSupply::new(options.battery_threshold);
+        let state_file = save_state.then(|| StateFile::new(&options.namespace));
+
+        let mut window = Self {
+            queue: queue.clone(),
             spb_buffer,
-            viewport,
+            shm_image,
             renderer,
             options,
+            base_options,
+            stream,
             surface,
             image,
+            backdrop,
+            overlays,
+            texts,
+            next_update,
+            power,
             scale: 1.,
+            transform: Transform::Normal,
+            texts_outdated: true,
+            full_damage: true,
+            text_images: Default::default(),
+            text_sizes: Default::default(),
+            text_rects: Default::default(),
+            sky_outdated: Default::default(),
+            sky_time,
+            frame_pending: Default::default(),
+            dirty: Default::default(),
             size: Default::default(),
-        })
+            orientation: Default::default(),
+            animation: Default::default(),
+            transform_outdated: Default::default(),
+            wl_shm,
+            cache,
+            cached_size,
+            color,
+            state_file,
+            scale_job: Default::default(),
+        };
+
+        if !deferred {
+            window.save_state();
+        }
+
+        if window.spb_buffer.is_none() && window.shm_image.is_none() && window.renderer.is_none() {
+            window.fallback_to_shm();
+        }
+
+        Ok(window)
+    }
+
+    /// Stop drawing to the window's layer shell surface after it was closed.
+    pub fn close(&mut self) {
+        self.size = Size::default();
+        self.animation.stop();
     }
 
-    /// Redraw the window.
+    /// Replace the closed layer shell surface with a new one.
+    ///
+    /// Images scaled for the old surface are loaded from the cache again, so
+    /// the new surface is usually drawn without decoding them.
+    pub fn reopen(&mut self, protocol_states: &ProtocolStates) {
+        let surface = Surface::new(protocol_states, &self.queue, &self.options);
+
+        // Move rendering to the new surface, before the old one is destroyed.
+        if let Some(renderer) = &mut self.renderer {
+            renderer.set_surface(surface.wl_surface().clone());
+            self.reload_textures();
+        }
+
+        self.surface = surface;
+
+        // Reset state tied to the old surface, the new one is set up from scratch.
+        self.size = Size::default();
+        self.scale = 1.;
+        self.transform = Transform::Normal;
+        self.transform_outdated = false;
+        self.frame_pending = false;
+        self.texts_outdated = true;
+        self.full_damage = true;
+        self.dirty = true;
+    }
+
+    /// Get the window's layer shell surface.
+    pub fn layer_surface(&self) -> &LayerSurface {
+        self.surface.layer()
+    }
+
+    /// Redraw the window, if its content has changed.
     pub fn draw(&mut self) {
+        // Skip redraws for unchanged or unconfigured windows.
+        if !self.dirty || self.size == Size::default() {
+            return;
+        }
+        self.dirty = false;
+
         // Update viewporter logical render size.
         //
         // NOTE: This must be done every time we draw with Sway; it is not
         // persisted when drawing with the same surface multiple times.
-        self.viewport.set_destination(self.size.width as i32, self.size.height as i32);
+        self.surface.viewport().set_destination(self.size.width as i32, self.size.height as i32);
+
+        let physical_size = self.size * self.scale;
+
+        self.animation.frame(Instant::now());
+
+        // Swap the image for a copy scaled to the surface.
+        self.update_cached_image(physical_size);
+
+        // Rasterize changed texts and update their logical bounds.
+        if self.texts_outdated {
+            self.rasterize_texts();
+        }
+        let text_rects = self.text_rects(physical_size);
+        let old_text_rects = mem::replace(&mut self.text_rects, text_rects);
 
-        // Mark entire window as damaged.
+        // Mark the entire window as damaged, or just the texts if nothing else changed.
         let wl_surface = self.surface.wl_surface();
-        wl_surface.damage(0, 0, self.size.width as i32, self.size.height as i32);
+        let damage: Vec<_> = if mem::take(&mut self.full_damage) {
+            vec![(Position::new(0, 0), self.size)]
+        } else {
+            old_text_rects.iter().chain(&self.text_rects).copied().collect()
+        };
+        for (position, size) in &damage {
+            wl_surface.damage(position.x, position.y, size.width as i32, size.height as i32);
+        }
+
+        // Rotate the buffer to match the output, avoiding compositor-side rotation.
+        if mem::take(&mut self.transform_outdated) {
+            wl_surface.set_buffer_transform(self.transform);
+        }
+
+        // Request the next animation frame, before EGL commits the buffer.
+        if self.animation.is_running() && !self.frame_pending {
+            wl_surface.frame(&self.queue, wl_surface.clone());
+            self.frame_pending = true;
+            self.full_damage = true;
+            self.dirty = true;
+        }
 
         // Render the window content.
-        match &self.spb_buffer {
-            Some(buffer) => wl_surface.attach(Some(buffer), 0, 0),
+        if let Some(buffer) = &self.spb_buffer {
+            wl_surface.attach(Some(buffer), 0, 0);
+        } else if self.shm_image.is_some() {
+            self.attach_shm(physical_size);
+        } else if self.renderer.is_some() {
+            let damage = physical_damage(&damage, self.scale, physical_size);
+            if let Err(err) = self.gl_draw(physical_size, &damage) {
+                error!("OpenGL rendering failed, falling back to software rendering: {err}");
+                self.fallback_to_shm();
+                self.attach_shm(physical_size);
+            }
+        }
+
+        // Apply surface changes.
+        self.surface.wl_surface().commit();
+    }
+
+    /// Replace the image with a copy scaled for the surface, using the cache.
+    ///
+    /// This allows skipping image decoding entirely on the next startup.
+    /// Images are scaled in the background, to avoid decoding and scaling
+    /// their source while drawing.
+    fn update_cached_image(&mut self, physical_size: Size) {
+        let cache = match &self.cache {
+            Some(cache) if self.options.image_path().is_some() => cache,
+            _ => return,
+        };
+
+        if self.cached_size == Some(physical_size) {
+            return;
+        }
+
+        // Load or decode the source again, since the current image's raw data is
+        // dropped once it was drawn.
+        if self.scale_job.as_ref().is_none_or(|job| job.physical_size() != physical_size) {
+            self.scale_job = ScaleJob::spawn(cache, &self.options, physical_size);
+        }
+    }
+
+    /// Apply the image scaled in the background, once it is done.
+    fn poll_scale_job(&mut self) {
+        let job = match self.scale_job.take_if(|job| job.poll()) {
+            Some(job) => job,
+            None => return,
+        };
+
+        // Ignore outdated images, since they were scaled for another size.
+        let physical_size = job.physical_size();
+        if physical_size != self.size * self.scale {
+            return;
+        }
+
+        match job.join() {
+            Some(image) => self.set_scaled_image(image, physical_size),
+            // Keep the current image, without retrying, if decoding failed.
             None => {
-                let physical_size = self.size * self.scale;
-                self.renderer.draw(physical_size, |renderer| {
-                    Self::gl_render(renderer, physical_size, &mut self.image, &self.options)
-                });
+                self.cached_size = Some(physical_size);
+                return;
             },
         }
 
-        // Apply surface changes.
-        wl_surface.commit();
+        self.full_damage = true;
+        self.dirty = true;
+        self.request_frame();
+    }
+
+    /// Replace the image with a copy scaled for a physical surface size.
+    fn set_scaled_image(&mut self, image: UnloadedImage, physical_size: Size) {
+        self.cached_size = Some(physical_size);
+        if let Some(shm_image) = &mut self.shm_image {
+            shm_image.set_image(image);
+        } else if let Some(current) = &mut self.image {
+            current.set_image(image);
+        }
+    }
+
+    /// Rasterize all texts at the current scale.
+    fn rasterize_texts(&mut self) {
+        self.texts_outdated = false;
+
+        let texts = self.texts.iter().zip(&self.options.texts);
+        let rasters: Vec<_> =
+            texts.map(|(layer, text)| layer.rasterize(text, self.scale)).collect();
+        self.text_sizes =
+            rasters.iter().map(|raster| Size::new(raster.width(), raster.height())).collect();
+
+        // Upload the texts to their renderer.
+        if let Some(shm_image) = &mut self.shm_image {
+            shm_image.set_texts(rasters);
+        } else {
+            for (i, raster) in rasters.into_iter().enumerate() {
+                match self.text_images.get_mut(i) {
+                    Some(image) => image.set_image(raster.into()),
+                    None => self.text_images.push(UnloadedImage::from(raster).into()),
+                }
+            }
+        }
+    }
+
+    /// Get the logical bounds of all texts.
+    fn text_rects(&self, physical_size: Size) -> Vec<(Position, Size)> {
+        let texts = self.options.texts.iter().zip(&self.text_sizes);
+        texts
+            .map(|(text, size)| {
+                let position = text.layout(physical_size, self.scale, *size);
+                let scale = self.scale as f32;
+
+                // Round outwards, to cover partially filled pixels.
+                let x = (position.x / scale).floor();
+                let y = (position.y / scale).floor();
+                let width = ((position.x + size.width as f32) / scale).ceil() - x;
+                let height = ((position.y + size.height as f32) / scale).ceil() - y;
+
+                (Position::new(x as i32, y as i32), Size::new(width as u32, height as u32))
+            })
+            .collect()
+    }
+
+    /// Attach the shared memory image to the surface.
+    fn attach_shm(&mut self, physical_size: Size) {
+        let shm_image = match &mut self.shm_image {
+            Some(shm_image) => shm_image,
+            None => return,
+        };
+
+        shm_image.set_transform(self.transform);
+
+        let (options, cache, stream) = (&self.options, self.cache.as_ref(), self.stream.as_deref());
+        let load_image = || reload_image(options, cache, stream, self.cached_size);
+
+        let (surface, scale) = (&self.surface, self.scale);
+        if let Err(err) =
+            shm_image.attach(&self.queue, surface, physical_size, scale, options, load_image)
+        {
+            error!("Failed to create shm buffer: {err}");
+        }
+    }
+
+    /// Draw the window using OpenGL.
+    ///
+    /// The EGL context and all textures are recreated when rendering fails,
+    /// to recover from context loss.
+    fn gl_draw(
+        &mut self,
+        physical_size: Size,
+        damage: &[(Position, Size)],
+    ) -> Result<(), RendererError> {
+        let mut retries = 0;
+        loop {
+            let renderer = match &mut self.renderer {
+                Some(renderer) => renderer,
+                None => return Ok(()),
+            };
+
+            let result = renderer.draw(physical_size, self.transform, damage, |renderer| {
+                let layers = GlLayers {
+                    animation: &self.animation,
+                    image: &mut self.image,
+                    backdrop: &mut self.backdrop,
+                    overlays: &mut self.overlays,
+                    texts: &mut self.text_images,
+                    color: self.color,
+                    sky_time: self.sky_time,
+                };
+                Self::gl_render(renderer, physical_size, self.scale, layers, &self.options)
+            });
+
+            match result {
+                Err(err) if retries < GL_RETRIES => {
+                    warn!("OpenGL rendering failed, recreating context: {err}");
+                    self.reload_textures();
+                    retries += 1;
+                },
+                result => return result,
+            }
+        }
+    }
+
+    /// Drop all OpenGL textures and load their images again.
+    ///
+    /// Textures are lost with their context, and the images' raw data is not
+    /// kept after uploading them.
+    fn reload_textures(&mut self) {
+        let overlays = self.overlays.iter_mut().chain(&mut self.text_images);
+        let images = self.image.iter_mut().chain(&mut self.backdrop);
+        for image in images.chain(overlays) {
+            image.texture = None;
+        }
+
+        // Load the background from the cache, and derive its backdrop again.
+        if let Some(current) = &mut self.image {
+            let (options, cache, stream) =
+                (&self.options, self.cache.as_ref(), self.stream.as_deref());
+            if let Some(image) = reload_image(options, cache, stream, self.cached_size) {
+                let backdrop = backdrop::create(options, Some(&image));
+                if let Some((current, backdrop)) = self.backdrop.as_mut().zip(backdrop) {
+                    current.set_image(backdrop.into());
+                }
+                current.set_image(image);
+            }
+        }
+
+        let overlays = self.reload_overlays();
+        for (image, overlay) in self.overlays.iter_mut().zip(overlays) {
+            image.set_image(overlay);
+        }
+
+        self.rasterize_texts();
+    }
+
+    /// Decode all overlay images again.
+    ///
+    /// Overlays after the first one which cannot be decoded are omitted.
+    fn reload_overlays(&self) -> Vec<UnloadedImage> {
+        let overlays =
+            self.options.overlays.iter().map(|overlay| UnloadedImage::new(&overlay.path));
+        overlays
+            .map_while(|overlay| {
+                overlay.inspect_err(|err| warn!("Failed to reload overlay: {err}")).ok()
+            })
+            .collect()
+    }
+
+    /// Permanently switch to rendering without OpenGL.
+    ///
+    /// Images are drawn using shared memory buffers, while plain colors use a
+    /// single-color image covering the entire surface.
+    fn fallback_to_shm(&mut self) {
+        self.renderer = None;
+        self.animation.stop();
+
+        // Load all images again, unless they were not uploaded yet.
+        let image = self.image.take().and_then(|image| {
+            let (options, cache, stream) =
+                (&self.options, self.cache.as_ref(), self.stream.as_deref());
+            image.pending.or_else(|| reload_image(options, cache, stream, self.cached_size))
+        });
+        let backdrop = self.backdrop.take().and_then(|backdrop| match backdrop.pending {
+            Some(backdrop) => Some(backdrop.to_rgba()),
+            None => backdrop::create(&self.options, image.as_ref()),
+        });
+        let overlays = self.reload_overlays().iter().map(UnloadedImage::to_rgba).collect();
+        self.overlays.clear();
+
+        let (color, sky_time) = (self.color, self.sky_time);
+        let mut shm_image = ShmImage::new(self.wl_shm.clone(), image, overlays, color, sky_time);
+        shm_image.set_backdrop(backdrop);
+        self.shm_image = Some(shm_image);
+
+        self.text_images.clear();
+        self.rasterize_texts();
     }
 
     /// Perform OpenGL rendering.
     fn gl_render(
         renderer: &Renderer,
         physical_size: Size,
-        image: &mut Option<Image>,
+        scale: f64,
+        layers: GlLayers<'_>,
         options: &Options,
     ) {
         // Render background color.
         let [r, g, b] = [
-            options.color.r as f32 / 255.,
-            options.color.g as f32 / 255.,
-            options.color.b as f32 / 255.,
+            layers.color.r as f32 / 255.,
+            layers.color.g as f32 / 255.,
+            layers.color.b as f32 / 255.,
         ];
         unsafe { gl::ClearColor(r, g, b, 1.) };
         unsafe { gl::Clear(gl::COLOR_BUFFER_BIT) };
 
-        // Render wallpaper image.
+        // Render procedural pattern.
+        if let Some(pattern) = &options.pattern {
+            let colors = pattern::colors(pattern, layers.color, &layers.sky_time);
+            let cell_size = pattern.size * scale as f32;
+            unsafe { renderer.draw_pattern(pattern.kind, colors, cell_size) };
+        }
 
-        let image = match image {
-            Some(image) => image,
-            None => return,
-        };
+        // Render blurred copy of the image around it.
+        if let Some(backdrop) = layers.backdrop {
+            let (position, size) = Fit::Cover.layout(physical_size, backdrop.size(), options.focus);
+            unsafe { backdrop.draw(renderer, position, size, 1.) };
+        }
 
-        let physical_size: Size<f32> = physical_size.into();
-        let image_size: Size<f32> = image.size().into();
-        let focus = options.focus;
-
-        // Fit image to screen dimensions.
-        let width_ratio = physical_size.width / image_size.width;
-        let height_ratio = physical_size.height / image_size.height;
-        let (position, size) = if width_ratio < height_ratio {
-            let width = image_size.width * height_ratio;
-            let x = (physical_size.width - width) * focus.x;
-            (Position::new(x, 0.), Size::new(width, physical_size.height))
-        } else {
-            let height = image_size.height * width_ratio;
-            let y = (physical_size.height - height) * focus.y;
-            (Position::new(0., y), Size::new(physical_size.width, height))
-        };
+        // Render wallpaper image, fit to screen dimensions.
+        if let Some(image) = layers.image {
+            let layout = options.image_layout(physical_size, image.size());
+            let (position, size) = layers.animation.layout(layout, Instant::now());
+
+            unsafe { image.draw(renderer, position, size, 1.) };
+        }
 
-        unsafe { renderer.draw_texture_at(image.texture(), position, size) };
+        // Render overlays on top.
+        for (overlay, image) in options.overlays.iter().zip(layers.overlays) {
+            let (position, size) = overlay.layout(physical_size, scale, image.size());
+            unsafe { image.draw(renderer, position, size, overlay.opacity) };
+        }
+
+        // Render texts at their rasterized size.
+        for (text, image) in options.texts.iter().zip(layers.texts) {
+            let position = text.layout(physical_size, scale, image.size());
+            unsafe { image.draw(renderer, position, image.size().into(), 1.) };
+        }
     }
 
     /// Update the window's logical size.
@@ -178,7 +645,18 @@ impl Window {
             return;
         }
 
+        self.start_transition();
+
         self.size = size;
+        self.full_damage = true;
+        self.dirty = true;
+
+        // Switch images and focus points with the orientation.
+        let orientation = Orientation::of(size);
+        if self.orientation != Some(orientation) {
+            self.orientation = Some(orientation);
+            self.set_orientation(orientation);
+        }
 
         // Update the window's opaque region.
         //
@@ -199,66 +677,369 @@ impl Window {
         }
 
         self.scale = scale;
+        self.texts_outdated = true;
+        self.full_damage = true;
+        self.dirty = true;
+
+        // Defer redraw to coalesce it with other pending changes.
+        if self.size != Size::default() {
+            self.request_frame();
+        }
+    }
+
+    /// Update the buffer transform preferred by the window's output.
+    pub fn set_transform(&mut self, transform: Transform) {
+        if self.transform == transform {
+            return;
+        }
+
+        self.transform = transform;
+        self.transform_outdated = true;
+        self.full_damage = true;
+        self.dirty = true;
+
+        // Defer redraw to coalesce it with other pending changes.
+        if self.size != Size::default() {
+            self.request_frame();
+        }
+    }
+
+    /// Start animating the image away from its current layout.
+    ///
+    /// Animations are skipped for the initial size, without OpenGL, and on low
+    /// battery, since they require redrawing every frame. On battery, they run
+    /// at a reduced frame rate.
+    fn start_transition(&mut self) {
+        let image_size = match &self.image {
+            Some(image) if self.renderer.is_some() && self.size != Size::default() => image.size(),
+            _ => return,
+        };
+
+        let duration = Duration::from_millis(self.options.transition_duration);
+        let layout = self.options.image_layout(self.size * self.scale, image_size);
+        self.animation.start(layout, duration, self.power.throttle());
+    }
+
+    /// Apply the image and focus point configured for an orientation.
+    fn set_orientation(&mut self, orientation: Orientation) {
+        let options = self.base_options.oriented(orientation);
+        let image_changed =
+            options.image != self.options.image || options.image_fd != self.options.image_fd;
+        let focus_changed = options.focus != self.options.focus;
+        self.options = options;
+
+        if image_changed {
+            self.load_image();
+        } else if focus_changed {
+            self.save_state();
+            if let Some(shm_image) = &mut self.shm_image {
+                shm_image.invalidate();
+            }
+        }
+    }
+
+    /// Reload the background image from disk.
+    ///
+    /// The current image is kept if the new one cannot be loaded.
+    pub fn reload_image(&mut self) {
+        if self.options.image_path().is_none() {
+            return;
+        }
+
+        self.load_image();
 
+        self.full_damage = true;
+        self.dirty = true;
         if self.size != Size::default() {
-            self.draw();
+            self.request_frame();
+        }
+    }
+
+    /// Load the background image, preferring a copy scaled for the surface.
+    ///
+    /// The current image is kept if the new one cannot be loaded.
+    fn load_image(&mut self) {
+        let physical_size = self.size * self.scale;
+        let cached = self
+            .options
+            .image_path()
+            .zip(self.cache.as_ref())
+            .and_then(|(path, cache)| cache.load(path, self.options.scaling(), physical_size));
+
+        let (image, cached_size) = match cached {
+            Some(image) => (image, Some(physical_size)),
+            None => match UnloadedImage::from_options(&self.options, self.stream.as_deref()) {
+                Ok(Some(image)) => (image, None),
+                Ok(None) => return,
+                Err(err) => {
+                    warn!("Failed to load image {:?}: {err}", self.options.image);
+                    return;
+                },
+            },
+        };
+
+        self.cached_size = cached_size;
+        self.set_image(image);
+    }
+
+    /// Replace the background image, updating everything derived from it.
+    fn set_image(&mut self, image: UnloadedImage) {
+        // Discard copies of the previous image scaled in the background.
+        self.scale_job = None;
+
+        palette::update(&self.options, &image);
+        self.color = palette::background(self.options.color, Some(&image));
+        self.save_state();
+        let backdrop = backdrop::create(&self.options, Some(&image));
+
+        if let Some(shm_image) = &mut self.shm_image {
+            shm_image.set_color(self.color);
+            shm_image.set_backdrop(backdrop);
+            shm_image.set_image(image);
+            return;
+        }
+
+        match &mut self.image {
+            Some(current) => current.set_image(image),
+            None => self.image = Some(image.into()),
         }
+        match (&mut self.backdrop, backdrop) {
+            (Some(current), Some(backdrop)) => current.set_image(backdrop.into()),
+            (current, backdrop) => {
+                *current = backdrop.map(|backdrop| UnloadedImage::from(backdrop).into())
+            },
+        }
+    }
+
+    /// Persist the displayed image, color and focus, if enabled.
+    fn save_state(&mut self) {
+        if let Some(state_file) = &mut self.state_file {
+            state_file.update(&self.options, self.color);
+        }
+    }
+
+    /// Update time-dependent content, like clocks.
+    pub fn update(&mut self) {
+        self.poll_scale_job();
+
+        // Updates are also due while images are scaled in the background.
+        let minute_passed = self.next_update.is_some_and(|update| update <= Instant::now());
+
+        let now = Local::now();
+        let mut changed = false;
+        for (layer, text) in self.texts.iter_mut().zip(&self.options.texts) {
+            changed |= layer.update(text, &now);
+        }
+
+        if self.next_update.is_some() {
+            self.next_update = Some(text::next_minute(&now));
+        }
+
+        // Redraw the sky gradient every few minutes, less often on battery.
+        let pattern_kind = self.options.pattern.map(|pattern| pattern.kind);
+        if pattern_kind == Some(PatternKind::Sky) {
+            self.sky_outdated |= minute_passed && now.minute().is_multiple_of(SKY_INTERVAL);
+
+            let due = match self.power.throttle() {
+                Throttle::None => true,
+                Throttle::Reduced => now.minute().is_multiple_of(SKY_INTERVAL_BATTERY),
+                Throttle::Frozen => false,
+            };
+
+            if self.sky_outdated && due {
+                if let Some(shm_image) = &mut self.shm_image {
+                    shm_image.set_sky_time(now);
+                }
+                self.sky_time = now;
+                self.sky_outdated = false;
+                self.full_damage = true;
+                changed = true;
+            }
+        }
+
+        if changed {
+            self.texts_outdated = true;
+            self.dirty = true;
+            if self.size != Size::default() {
+                self.request_frame();
+            }
+        }
+    }
+
+    /// Get the time of the next content update.
+    pub fn next_update(&self) -> Option<Instant> {
+        let scale_job = self.scale_job.as_ref().map(ScaleJob::next_poll);
+        self.next_update.into_iter().chain(scale_job).min()
+    }
+
+    /// Handle frame callbacks.
+    pub fn frame(&mut self) {
+        self.frame_pending = false;
+
+        // Skip animation frames, to reduce the frame rate on battery.
+        if self.animation.is_throttled(Instant::now()) {
+            self.request_frame();
+            return;
+        }
+
+        self.draw();
+    }
+
+    /// Request a new frame callback.
+    ///
+    /// Frames should only be requested for pending changes, to avoid waking
+    /// up for static content.
+    fn request_frame(&mut self) {
+        if self.frame_pending {
+            return;
+        }
+        self.frame_pending = true;
+
+        let wl_surface = self.surface.wl_surface();
+        wl_surface.frame(&self.queue, wl_surface.clone());
+        wl_surface.commit();
     }
 }
 
+/// Layers rendered with OpenGL.
+struct GlLayers<'a> {
+    animation: &'a LayoutAnimation,
+    image: &'a mut Option<Image>,
+    backdrop: &'a mut Option<Image>,
+    overlays: &'a mut [Image],
+    texts: &'a mut [Image],
+    color: cli::Rgb,
+    sky_time: DateTime<Local>,
+}
+
 /// OpenGL renderable image.
-enum Image {
-    Loaded(Texture),
-    Unloaded(UnloadedImage),
+///
+/// The raw image data is dropped after uploading it, so it must be loaded
+/// again to recreate the texture after a context loss.
+struct Image {
+    texture: Option<Texture>,
+    /// Raw image data waiting to be uploaded.
+    pending: Option<UnloadedImage>,
+    size: Size,
 }
 
 impl Image {
-    /// Get this image's OpenGL texture.
+    /// Draw the image's OpenGL texture, uploading it if necessary.
+    ///
+    /// Nothing is drawn if the texture was lost without loading the image
+    /// again.
     ///
     /// # Safety
     ///
     /// This must be called with the correct context made current, or the image
     /// will be loaded into an unrelated context.
-    unsafe fn texture(&mut self) -> &Texture {
-        // Load the OpenGL texture.
-        if let Self::Unloaded(image) = self {
+    unsafe fn draw(
+        &mut self,
+        renderer: &Renderer,
+        position: Position<f32>,
+        size: Size<f32>,
+        opacity: f32,
+    ) {
+        // Replace the texture of the previous image.
+        if let Some(image) = self.pending.take() {
+            if let Some(texture) = self.texture.take() {
+                unsafe { texture.delete() };
+            }
             let texture = Texture::new(&image.bytes, image.width, image.height, image.gl_format);
-            *self = Self::Loaded(texture);
+            self.texture = Some(texture);
         }
 
-        match self {
-            Self::Loaded(texture) => texture,
-            Self::Unloaded(_) => unreachable!(),
+        if let Some(texture) = &self.texture {
+            unsafe { renderer.draw_texture_at(texture, position, size, opacity) };
         }
     }
 
     /// Source image dimensions.
     fn size(&self) -> Size {
-        match &self {
-            Self::Loaded(texture) => Size::new(texture.width, texture.height),
-            Self::Unloaded(image) => Size::new(image.width, image.height),
-        }
+        self.size
+    }
+
+    /// Replace the image, uploading it on the next draw.
+    fn set_image(&mut self, image: UnloadedImage) {
+        self.size = image.size();
+        self.pending = Some(image);
     }
 }
 
 impl From<UnloadedImage> for Image {
     fn from(image: UnloadedImage) -> Self {
-        Self::Unloaded(image)
+        Self { size: image.size(), pending: Some(image), texture: None }
     }
 }
 
 /// Raw wallpaper image data.
-struct UnloadedImage {
-    bytes: Vec<u8>,
+pub struct UnloadedImage {
+    bytes: ImageBytes,
     width: u32,
     height: u32,
     gl_format: u32,
 }
 
+impl From<RgbaImage> for UnloadedImage {
+    fn from(image: RgbaImage) -> Self {
+        let (width, height) = image.dimensions();
+        Self { bytes: ImageBytes::Owned(image.into_raw()), width, height, gl_format: gl::RGBA }
+    }
+}
+
 impl UnloadedImage {
-    fn new<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-        let image = ImageReader::open(path)?.decode()?;
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let image = ImageReader::open(path)?.with_guessed_format()?.decode()?;
+        Ok(Self::from_dynamic(image))
+    }
+
+    /// Load the background image configured in the options.
+    ///
+    /// Images read from stdin or `--image-fd` are decoded from `stream`, the
+    /// content returned by [`Self::read_stream`]. The image is cropped to the
+    /// `--crop` region.
+    pub fn from_options(options: &Options, stream: Option<&[u8]>) -> Result<Option<Self>, Error> {
+        let is_stream = options.image.is_some() || options.image_fd.is_some();
+        let image = if let Some(path) = options.image_path() {
+            Self::new(path)?
+        } else if let Some(bytes) = stream.filter(|_| is_stream) {
+            Self::from_bytes(bytes)?
+        } else {
+            return Ok(None);
+        };
 
+        Ok(Some(image.crop(options.crop)))
+    }
+
+    /// Read the encoded background image from stdin or `--image-fd`.
+    ///
+    /// Streams cannot be rewound, so their content must be kept to decode the
+    /// image again.
+    pub fn read_stream(options: &Options) -> Result<Option<Vec<u8>>, Error> {
+        let mut bytes = Vec::new();
+        if options.image_path().is_some() {
+            return Ok(None);
+        } else if options.image.is_some() {
+            io::stdin().lock().read_to_end(&mut bytes)?;
+        } else if let Some(fd) = options.image_fd {
+            // Duplicate the FD, to avoid taking ownership of it.
+            let fd = unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()?;
+            File::from(fd).read_to_end(&mut bytes)?;
+        } else {
+            return Ok(None);
+        }
+
+        Ok(Some(bytes))
+    }
+
+    /// Decode an image from memory, guessing its format from the content.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let image = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?.decode()?;
+        Ok(Self::from_dynamic(image))
+    }
+
+    /// Convert a decoded image into raw image data.
+    fn from_dynamic(image: DynamicImage) -> Self {
         let width = image.width();
         let height = image.height();
 
@@ -269,6 +1050,152 @@ impl UnloadedImage {
             _ => (image.into_rgba8().into_raw(), gl::RGBA),
         };
 
-        Ok(Self { gl_format, width, height, bytes })
+        Self { gl_format, width, height, bytes: ImageBytes::Owned(bytes) }
+    }
+
+    /// Crop the image to a region, if any.
+    pub fn crop(self, crop: Option<Crop>) -> Self {
+        let (position, size) = match crop {
+            Some(crop) => crop.rect(self.size()),
+            None => return self,
+        };
+
+        let bytes_per_pixel = match self.gl_format {
+            gl::LUMINANCE_ALPHA => 2,
+            gl::LUMINANCE => 1,
+            gl::RGB => 3,
+            _ => 4,
+        };
+        let stride = self.width as usize * bytes_per_pixel;
+        let row_len = size.width as usize * bytes_per_pixel;
+
+        let mut bytes = Vec::with_capacity(row_len * size.height as usize);
+        for y in position.y..position.y + size.height {
+            let start = y as usize * stride + position.x as usize * bytes_per_pixel;
+            bytes.extend_from_slice(&self.bytes[start..start + row_len]);
+        }
+
+        let bytes = ImageBytes::Owned(bytes);
+        Self { bytes, width: size.width, height: size.height, gl_format: self.gl_format }
+    }
+
+    /// Use RGBA data from a memory-mapped file, starting at `offset`.
+    pub fn from_mapped(mmap: Mmap, offset: usize, size: Size) -> Self {
+        let bytes = ImageBytes::Mapped(mmap, offset);
+        Self { bytes, width: size.width, height: size.height, gl_format: gl::RGBA }
+    }
+
+    /// Source image dimensions.
+    pub fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+
+    /// Convert the image to RGBA.
+    pub fn to_rgba(&self) -> RgbaImage {
+        self.resize(self.size())
+    }
+
+    /// Scale the image to a new size, converting it to RGBA.
+    pub fn resize(&self, size: Size) -> RgbaImage {
+        match self.gl_format {
+            gl::LUMINANCE_ALPHA => self.resize_as::<LumaA<u8>>(size),
+            gl::LUMINANCE => self.resize_as::<Luma<u8>>(size),
+            gl::RGB => self.resize_as::<Rgb<u8>>(size),
+            _ => self.resize_as::<Rgba<u8>>(size),
+        }
+    }
+
+    /// Scale the image down to fit within `max_size` in both dimensions.
+    ///
+    /// The aspect ratio is preserved, and smaller images are not scaled up.
+    pub fn thumbnail(&self, max_size: u32) -> RgbaImage {
+        let size = self.size();
+        let factor = (max_size as f64 / size.width.max(size.height) as f64).min(1.);
+        self.resize(Size::new(
+            ((size.width as f64 * factor).round() as u32).max(1),
+            ((size.height as f64 * factor).round() as u32).max(1),
+        ))
     }
+
+    /// Scale the image, interpreting its bytes using the pixel format `P`.
+    fn resize_as<P: Pixel<Subpixel = u8> + 'static>(&self, size: Size) -> RgbaImage {
+        let image =
+            ImageBuffer::<P, _>::from_raw(self.width, self.height, &self.bytes[..]).unwrap();
+        let scaled = imageops::resize(&image, size.width, size.height, FilterType::Triangle);
+        RgbaImage::from_fn(size.width, size.height, |x, y| scaled.get_pixel(x, y).to_rgba())
+    }
+}
+
+/// Storage of raw image data.
+enum ImageBytes {
+    Owned(Vec<u8>),
+    /// Memory-mapped file, with the pixels starting at an offset.
+    Mapped(Mmap, usize),
+}
+
+impl Deref for ImageBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Owned(bytes) => bytes,
+            Self::Mapped(mmap, offset) => &mmap[*offset..],
+        }
+    }
+}
+
+/// Load the background image again, after its raw data was dropped.
+///
+/// Images scaled for the physical surface size `scaled_size` are loaded from
+/// the cache, or decoded and scaled again if they are no longer cached.
+fn reload_image(
+    options: &Options,
+    cache: Option<&ImageCache>,
+    stream: Option<&[u8]>,
+    scaled_size: Option<Size>,
+) -> Option<UnloadedImage> {
+    let cached = options
+        .image_path()
+        .zip(cache)
+        .zip(scaled_size)
+        .and_then(|((path, cache), size)| cache.load(path, options.scaling(), size));
+    if cached.is_some() {
+        return cached;
+    }
+
+    let image = match UnloadedImage::from_options(options, stream) {
+        Ok(image) => image?,
+        Err(err) => {
+            warn!("Failed to reload image {:?}: {err}", options.image);
+            return None;
+        },
+    };
+
+    match scaled_size {
+        Some(size) => Some(cache::scale_to_fit(&image, size, options).into()),
+        None => Some(image),
+    }
+}
+
+/// Convert logical damage rectangles to physical surface coordinates.
+///
+/// Rectangles are rounded outwards and clamped to the surface.
+fn physical_damage(
+    damage: &[(Position, Size)],
+    scale: f64,
+    physical_size: Size,
+) -> Vec<(Position, Size)> {
+    let (max_x, max_y) = (physical_size.width as f64, physical_size.height as f64);
+    damage
+        .iter()
+        .map(|(position, size)| {
+            let x = (position.x as f64 * scale).floor().clamp(0., max_x);
+            let y = (position.y as f64 * scale).floor().clamp(0., max_y);
+            let right = ((position.x as f64 + size.width as f64) * scale).ceil().clamp(x, max_x);
+            let bottom = ((position.y as f64 + size.height as f64) * scale).ceil().clamp(y, max_y);
+
+            let size = Size::new((right - x) as u32, (bottom - y) as u32);
+            (Position::new(x as i32, y as i32), size)
+        })
+        .collect()
 }